@@ -11,6 +11,14 @@ function toggleState(buttonId) {
   window.sharedWebSocket.send(JSON.stringify({ control_name: buttonId, state: newState }));
 }
 
+function setLightTheme(theme) {
+  if (window.sharedWebSocket.readyState != WebSocket.OPEN) {
+    console.error(`Websocket is not open in light theme [[${theme}]]`);
+    return;
+  }
+  window.sharedWebSocket.send(JSON.stringify({ theme: theme }));
+}
+
 function setupWebSocket() {
   const hostname = window.location.hostname;
   const port = window.location.port;
@@ -55,6 +63,9 @@ function read_state(state_data) {
         button.classList.remove('on', 'off');
         button.classList.add(state? 'on': 'off');
     }
+    for (const button of document.getElementsByClassName('light-theme')) {
+        button.classList.toggle('on', button.id === `light-${response.lights.theme}`);
+    }
 }
 
 async function showLog() {
@@ -0,0 +1,543 @@
+// A simulated EasyTouch panel: broadcasts the status, clock, pump and IntelliChem packets and answers
+// circuit, pump and chlorinator commands, so the service can be run without a pool.
+
+use chrono::{Local, TimeDelta, Timelike};
+use clap::Parser;
+use pentair_cargo::config::config_json::PortParameters;
+use pentair_cargo::error::PoolError;
+use pentair_cargo::pool::board::{Board, EasyTouch};
+use pentair_cargo::pool::frame::{DecoderCounters, Frame, FrameDecoder};
+use pentair_cargo::pool::message::chlorinator::{self, ChlorinatorMessage};
+use pentair_cargo::pool::message::clock::{PanelClock, CLOCK_ACTION, SET_CLOCK_ACTION};
+use pentair_cargo::pool::message::equipment::{
+    self, SolarConfig, Valve, ValveAssignment, ValveConfig,
+};
+use pentair_cargo::pool::message::heat::{
+    HeatMode, HeatSettings, HEAT_STATUS_ACTION, HEAT_STATUS_REQUEST, SET_HEAT_ACTION,
+};
+use pentair_cargo::pool::message::intellichem::{
+    ChemistryState, DosingState, CHEM_STATUS_ACTION, FIRST_CHEM_ADDRESS,
+};
+use pentair_cargo::pool::message::light::{self, GroupLight, LightColor, LightGroup};
+use pentair_cargo::pool::message::panel_config::{self, CircuitDefinition};
+use pentair_cargo::pool::message::pump_state::{
+    PumpState, FIRST_PUMP_ADDRESS, PUMP_STATUS_ACTION, REMOTE_CONTROL_ACTION, RUN_ACTION,
+    SET_MODE_ACTION, SET_SPEED_ACTION,
+};
+use pentair_cargo::pool::message::schedule::{self, Schedule};
+use pentair_cargo::pool::message::system_state::SystemState;
+use pentair_cargo::pool::message::{
+    self, PacketType, ProtocolPacket, ACK_ACTION, BROADCAST_ADDRESS, STATUS_ACTION,
+};
+use pentair_cargo::pool::serial::serial_port;
+use pentair_cargo::pool::transport::{is_timeout, Transport};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+struct Cli {
+    /// Serial device to talk over, e.g. one end of a pty pair made by socat.
+    #[arg(long)]
+    port: Option<String>,
+
+    /// Listen for the service on this address instead, as an RS-485 network bridge.
+    #[arg(long, default_value = "127.0.0.1:9801")]
+    listen: String,
+
+    /// How often the broadcasts are sent.
+    #[arg(long, default_value = "2000")]
+    interval_ms: u64,
+}
+
+struct Panel {
+    state: SystemState,
+    // Set while the service runs the pump: running and the speed it asked for.
+    remote_pump: Option<(bool, u16)>,
+    // How far the panel clock is from the host, it starts a few minutes late.
+    clock_offset: TimeDelta,
+    heat: HeatSettings,
+    // Schedule payloads by the slot id.
+    schedules: BTreeMap<u8, Vec<u8>>,
+    light_group: LightGroup,
+}
+
+impl Panel {
+    fn new() -> Self {
+        let mut state = SystemState::new();
+        state.set_circuit(6, true);
+        state.set_temperatures(78, 72, 85);
+        Panel {
+            state,
+            remote_pump: None,
+            clock_offset: TimeDelta::minutes(-7),
+            heat: HeatSettings {
+                pool_setpoint: 82,
+                pool_mode: HeatMode::SolarPreferred,
+                spa_setpoint: 102,
+                spa_mode: HeatMode::Heater,
+            },
+            // The pool runs from 8:00 to 17:30 on weekdays, the spa has a 2:15 egg timer.
+            schedules: BTreeMap::from([
+                (1, vec![0x01, 0x06, 0x08, 0x00, 0x11, 0x1E, 0x3E]),
+                (2, vec![0x02, 0x01, 0x19, 0x00, 0x02, 0x0F, 0x00]),
+            ]),
+            light_group: LightGroup {
+                lights: vec![GroupLight {
+                    circuit: 2,
+                    position: 1,
+                    color: LightColor::Blue,
+                    swim_delay: 5,
+                }],
+            },
+        }
+    }
+
+    fn status_frame(&self) -> Vec<u8> {
+        let now = Local::now();
+        encode_frame(
+            0x01,
+            BROADCAST_ADDRESS,
+            message::PANEL_ADDRESS,
+            STATUS_ACTION,
+            &self
+                .state
+                .encode_payload(now.hour() as u8, now.minute() as u8),
+        )
+    }
+
+    fn clock_frame(&self) -> Vec<u8> {
+        let clock = PanelClock::from_time(&(Local::now() + self.clock_offset), true);
+        encode_frame(
+            0x01,
+            BROADCAST_ADDRESS,
+            message::PANEL_ADDRESS,
+            CLOCK_ACTION,
+            &clock.encode_payload(),
+        )
+    }
+
+    fn heat_frame(&self) -> Vec<u8> {
+        encode_frame(
+            0x01,
+            BROADCAST_ADDRESS,
+            message::PANEL_ADDRESS,
+            HEAT_STATUS_ACTION,
+            &self.heat_payload(),
+        )
+    }
+
+    fn heat_payload(&self) -> Vec<u8> {
+        self.heat.encode_payload(78, 78, 72, 85)
+    }
+
+    fn pump_frame(&self) -> Vec<u8> {
+        let now = Local::now();
+        let pool_on = self
+            .state
+            .get_controls_state()
+            .iter()
+            .any(|(name, on)| name == "pool" && *on);
+        let (running, rpm) = self.remote_pump.unwrap_or((pool_on, 2500));
+        let pump = PumpState {
+            address: FIRST_PUMP_ADDRESS,
+            running,
+            mode: 0,
+            drive_state: 2,
+            watts: if running { rpm / 3 } else { 0 },
+            rpm: if running { rpm } else { 0 },
+            gpm: if running { (rpm / 60) as u8 } else { 0 },
+            percent: 0,
+            error: 0,
+            timer: 0,
+        };
+        encode_frame(
+            0x00,
+            message::PANEL_ADDRESS,
+            pump.address,
+            PUMP_STATUS_ACTION,
+            &pump.encode_payload(now.hour() as u8, now.minute() as u8),
+        )
+    }
+
+    // IntelliChem answering the panel: the pH is a little high and it doses acid.
+    fn chemistry_frame(&self) -> Vec<u8> {
+        let chemistry = ChemistryState {
+            address: FIRST_CHEM_ADDRESS,
+            ph: 7.6,
+            orp: 710,
+            ph_setpoint: 7.5,
+            orp_setpoint: 700,
+            ph_tank: 4,
+            orp_tank: 5,
+            lsi: 0.12,
+            calcium_hardness: 250,
+            cyanuric_acid: 40,
+            alkalinity: 90,
+            salt_ppm: 3200,
+            temperature: 78,
+            flow_alarm: false,
+            alarms: vec![],
+            warnings: vec![],
+            ph_dosing: DosingState::Dosing,
+            orp_dosing: DosingState::Monitoring,
+        };
+        encode_frame(
+            0x00,
+            message::PANEL_ADDRESS,
+            chemistry.address,
+            CHEM_STATUS_ACTION,
+            &chemistry.encode_payload(),
+        )
+    }
+
+    fn broadcasts(&self) -> Vec<Vec<u8>> {
+        vec![
+            self.status_frame(),
+            self.clock_frame(),
+            self.heat_frame(),
+            self.pump_frame(),
+            self.chemistry_frame(),
+        ]
+    }
+
+    /// Handles a packet from the bus, returns the frames to send back.
+    fn process(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        let packet = match EasyTouch.decode_packet(packet) {
+            Ok(packet) => packet,
+            Err(e) => {
+                println!("Bad packet {:02x?}: {}", packet, e);
+                return vec![];
+            }
+        };
+        match &packet.decoded {
+            PacketType::CircuitStatusChange { circuit, state }
+                if packet.get_destination() == message::PANEL_ADDRESS =>
+            {
+                println!("Circuit {} -> {}", circuit, state);
+                if !self.state.set_circuit(*circuit, *state) {
+                    return vec![];
+                }
+                let ack = encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[message::SET_CIRCUIT_ACTION],
+                );
+                vec![ack, self.status_frame()]
+            }
+            PacketType::SetClock(clock) if packet.get_destination() == message::PANEL_ADDRESS => {
+                println!("Clock set to {}", clock.time);
+                self.clock_offset = clock.time - Local::now().naive_local();
+                let ack = encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[SET_CLOCK_ACTION],
+                );
+                vec![ack, self.clock_frame()]
+            }
+            PacketType::SetHeat(heat) if packet.get_destination() == message::PANEL_ADDRESS => {
+                println!("Heat set to {:?}", heat);
+                self.heat = heat.clone();
+                let ack = encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[SET_HEAT_ACTION],
+                );
+                vec![ack, self.heat_frame()]
+            }
+            PacketType::SetSchedule { id, schedule }
+                if packet.get_destination() == message::PANEL_ADDRESS =>
+            {
+                println!("Schedule {} set to {:?}", id, schedule);
+                match schedule {
+                    Some(schedule) => self.schedules.insert(*id, schedule.encode_payload()),
+                    None => self.schedules.remove(id),
+                };
+                vec![encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[schedule::SET_SCHEDULE_ACTION],
+                )]
+            }
+            PacketType::LightCommand(theme)
+                if packet.get_destination() == message::PANEL_ADDRESS =>
+            {
+                println!("Lights set to {:?}", theme);
+                vec![encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[light::LIGHT_COMMAND_ACTION],
+                )]
+            }
+            PacketType::SetLightGroup(group)
+                if packet.get_destination() == message::PANEL_ADDRESS =>
+            {
+                println!("Light group set to {:?}", group);
+                self.light_group = group.clone();
+                vec![encode_frame(
+                    0x01,
+                    packet.get_source(),
+                    message::PANEL_ADDRESS,
+                    ACK_ACTION,
+                    &[light::SET_LIGHT_GROUP_ACTION],
+                )]
+            }
+            PacketType::ConfigRequest(_) | PacketType::RemoteLayoutRequest
+                if packet.get_destination() == message::PANEL_ADDRESS =>
+            {
+                self.process_config_request(&packet)
+            }
+            _ if packet.get_destination() == FIRST_PUMP_ADDRESS => self.process_pump(&packet),
+            _ => vec![],
+        }
+    }
+
+    /// Circuit definition: function code, freeze protection and the name id.
+    fn circuit_definition(number: u8) -> (u8, bool, u8) {
+        match number {
+            1 => (1, false, 72),  // SPA
+            2 => (16, false, 63), // POOL LIGHT, IntelliBrite
+            3 => (0, false, 200), // Custom name 0
+            4 => (0, false, 5),   // AUX 3
+            6 => (2, true, 61),   // POOL
+            7 => (0, true, 46),   // JETS
+            _ => (19, false, 53), // NOT USED
+        }
+    }
+
+    /// Answers the configuration requests.
+    fn process_config_request(&self, packet: &ProtocolPacket) -> Vec<Vec<u8>> {
+        let argument = packet.get_payload().first().copied().unwrap_or_default();
+        let (action, payload) = match packet.get_action() {
+            panel_config::CIRCUIT_DEFINITION_REQUEST => {
+                let (function, freeze, name_id) = Self::circuit_definition(argument);
+                (
+                    panel_config::CIRCUIT_DEFINITION_ACTION,
+                    CircuitDefinition::encode_payload(argument, function, freeze, name_id),
+                )
+            }
+            panel_config::CUSTOM_NAME_REQUEST => {
+                let name = if argument == 0 {
+                    "WATERSLIDE".to_string()
+                } else {
+                    format!("USERNAME-{:02}", argument + 1)
+                };
+                (
+                    panel_config::CUSTOM_NAME_ACTION,
+                    panel_config::encode_custom_name(argument, &name),
+                )
+            }
+            panel_config::REMOTE_LAYOUT_REQUEST => (
+                panel_config::REMOTE_LAYOUT_ACTION,
+                vec![argument, 6, 1, 2, 7],
+            ),
+            // Valve A is the solar valve, the intake and return rotate for the spa.
+            equipment::VALVE_CONFIG_REQUEST => (
+                equipment::VALVE_CONFIG_ACTION,
+                ValveConfig {
+                    intake_return: true,
+                    pump_off_during_rotation: true,
+                    valves: vec![
+                        Valve {
+                            name: "A".to_string(),
+                            assignment: ValveAssignment::Solar,
+                        },
+                        Valve {
+                            name: "B".to_string(),
+                            assignment: ValveAssignment::NotUsed,
+                        },
+                    ],
+                }
+                .encode_payload(),
+            ),
+            equipment::SOLAR_CONFIG_REQUEST => (
+                equipment::SOLAR_CONFIG_ACTION,
+                SolarConfig {
+                    solar: true,
+                    heat_pump: false,
+                    freeze_protect: false,
+                    night_cooling: false,
+                    start_delta: 6,
+                    stop_delta: 3,
+                }
+                .encode_payload(),
+            ),
+            HEAT_STATUS_REQUEST => (HEAT_STATUS_ACTION, self.heat_payload()),
+            light::LIGHT_GROUP_REQUEST => {
+                (light::LIGHT_GROUP_ACTION, self.light_group.encode_payload())
+            }
+            schedule::SCHEDULE_REQUEST => (
+                schedule::SCHEDULE_ACTION,
+                self.schedules
+                    .get(&argument)
+                    .cloned()
+                    .unwrap_or_else(|| Schedule::encode_empty_payload(argument)),
+            ),
+            _ => return vec![],
+        };
+        vec![encode_frame(
+            0x01,
+            packet.get_source(),
+            message::PANEL_ADDRESS,
+            action,
+            &payload,
+        )]
+    }
+
+    /// Answers as an IntelliChlor with 3200 ppm of salt.
+    fn process_chlorinator(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        if ChlorinatorMessage::get_destination(packet) != chlorinator::CHLORINATOR_ADDRESS {
+            return vec![];
+        }
+        match ChlorinatorMessage::decode(packet) {
+            Ok(ChlorinatorMessage::SetOutput(output)) => {
+                println!("Chlorinator output {}%", output);
+                vec![chlorinator::encode_packet(
+                    chlorinator::MASTER_ADDRESS,
+                    chlorinator::STATUS_ACTION,
+                    &[64, 0],
+                )]
+            }
+            Ok(ChlorinatorMessage::Probe) => vec![chlorinator::encode_packet(
+                chlorinator::MASTER_ADDRESS,
+                chlorinator::PROBE_RESPONSE_ACTION,
+                &[0x00],
+            )],
+            _ => vec![],
+        }
+    }
+
+    /// The pump confirms every command with the same action.
+    fn process_pump(&mut self, packet: &ProtocolPacket) -> Vec<Vec<u8>> {
+        let payload = packet.get_payload().to_vec();
+        let reply = match (packet.get_action(), payload.as_slice()) {
+            (REMOTE_CONTROL_ACTION, [0xFF]) => {
+                let current = self.remote_pump.unwrap_or((false, 2500));
+                self.remote_pump = Some(current);
+                payload.clone()
+            }
+            (REMOTE_CONTROL_ACTION, _) => {
+                self.remote_pump = None;
+                payload.clone()
+            }
+            (SET_SPEED_ACTION, [0x02, 0xC4, hi, lo]) => {
+                let rpm = u16::from_be_bytes([*hi, *lo]);
+                if let Some((_, speed)) = self.remote_pump.as_mut() {
+                    *speed = rpm;
+                }
+                vec![*hi, *lo]
+            }
+            (SET_SPEED_ACTION, [_, _, hi, lo]) => vec![*hi, *lo],
+            (RUN_ACTION, [run]) => {
+                if let Some((running, _)) = self.remote_pump.as_mut() {
+                    *running = *run == 0x0A;
+                }
+                payload.clone()
+            }
+            (SET_MODE_ACTION, _) => payload.clone(),
+            _ => return vec![],
+        };
+        println!("Pump command {:#04x} {:02x?}", packet.get_action(), payload);
+        vec![encode_frame(
+            0x00,
+            packet.get_source(),
+            FIRST_PUMP_ADDRESS,
+            packet.get_action(),
+            &reply,
+        )]
+    }
+}
+
+// The simulator only sends short payloads, a longer one is a bug here.
+fn encode_frame(protocol_version: u8, dest: u8, source: u8, action: u8, payload: &[u8]) -> Vec<u8> {
+    ProtocolPacket::encode_packet(protocol_version, dest, source, action, payload)
+        .expect("Simulated payloads fit in a packet")
+}
+
+/// Talks to one connection until it fails.
+fn serve<T: Transport>(
+    mut port: T,
+    panel: &mut Panel,
+    interval: Duration,
+) -> Result<(), PoolError> {
+    port.set_read_timeout(Duration::from_millis(50))?;
+    let mut last_broadcast: Option<Instant> = None;
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = [0; 64];
+    loop {
+        let replies = match port.read(&mut buffer) {
+            Ok(0) => {
+                return Err(PoolError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed",
+                )))
+            }
+            Ok(len) => {
+                let mut replies = Vec::new();
+                decoder.push(&buffer[..len], &mut |frame| match frame {
+                    Frame::Packet(packet) => replies.extend(panel.process(packet)),
+                    Frame::Chlorinator(packet) => replies.extend(panel.process_chlorinator(packet)),
+                });
+                replies
+            }
+            Err(e) if is_timeout(&e) => {
+                decoder.idle();
+                let counters = decoder.take_counters();
+                if counters != DecoderCounters::default() {
+                    println!("Discarded {:?}", counters);
+                }
+                if last_broadcast.is_some_and(|t| t.elapsed() < interval) {
+                    continue;
+                }
+                last_broadcast = Some(Instant::now());
+                panel.broadcasts()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        for frame in replies {
+            port.write_all(&frame)?;
+        }
+    }
+}
+
+fn main() {
+    let args = Cli::parse();
+    let interval = Duration::from_millis(args.interval_ms);
+    let mut panel = Panel::new();
+    match args.port {
+        Some(port_name) => {
+            let parameters: PortParameters =
+                serde_json::from_value(serde_json::json!({ "port_name": port_name })).unwrap();
+            let port = serial_port(&parameters).expect("Failed to open the port");
+            if let Err(e) = serve(port, &mut panel, interval) {
+                println!("Port failed: {}", e);
+            }
+        }
+        None => {
+            let listener = TcpListener::bind(&args.listen).expect("Failed to listen");
+            println!("Listening on {}", args.listen);
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        println!("Connection from {:?}", stream.peer_addr());
+                        if let Err(e) = serve(stream, &mut panel, interval) {
+                            println!("Connection closed: {}", e);
+                        }
+                    }
+                    Err(e) => println!("Failed to accept: {}", e),
+                }
+            }
+        }
+    }
+}
@@ -1,45 +1,232 @@
-// A simple application to send debug sequence to the port.
+// A simple application to look at the bus and to send debug sequences to the port.
 
-use clap::Parser;
-use serial::{self, SerialPort};
-use std::io::Write;
+use chrono::Local;
+use clap::{Args, Parser, Subcommand};
+use pentair_cargo::config::config_json::PortParameters;
+use pentair_cargo::pool::board::{Board, BoardKind};
+use pentair_cargo::pool::frame::{Frame, FrameDecoder};
+use pentair_cargo::pool::message::dissector::{describe, describe_chlorinator, hex_string};
+use pentair_cargo::pool::message::PacketBuilder;
+use pentair_cargo::pool::serial::open_transport;
+use pentair_cargo::pool::transport::{is_timeout, Transport};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+/// A quiet bus for this long ends a frame that is not complete.
+const IDLE_TIME: Duration = Duration::from_millis(100);
 
 #[derive(Parser)]
 struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the fields of the frames in hex, e.g. `FF00FFA5 01 10 24 86 02 0601 0169`. A
+    /// packet without the header and the checksum is decoded too.
+    Decode {
+        hex: Vec<String>,
+        #[arg(long, default_value = "easy_touch", value_parser = parse_board)]
+        board: BoardKind,
+    },
+    /// Prints the frames on the bus as they come.
+    Sniff {
+        #[command(flatten)]
+        connection: Connection,
+        #[arg(long, default_value = "easy_touch", value_parser = parse_board)]
+        board: BoardKind,
+    },
+    /// Builds a frame with the checksum and writes it to the bus. Numbers are decimal or hex
+    /// with 0x.
+    Send {
+        #[command(flatten)]
+        connection: Connection,
+        #[arg(long, value_parser = parse_byte)]
+        dest: u8,
+        #[arg(long, value_parser = parse_byte, default_value = "0x21")]
+        source: u8,
+        #[arg(long, value_parser = parse_byte)]
+        action: u8,
+        /// Payload in hex.
+        #[arg(long, default_value = "")]
+        payload: String,
+        #[arg(long, value_parser = parse_byte, default_value = "1")]
+        version: u8,
+        /// Only print the frame.
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long, default_value = "easy_touch", value_parser = parse_board)]
+        board: BoardKind,
+    },
+    /// Writes the bytes in hex to the bus as they are.
+    Raw {
+        #[command(flatten)]
+        connection: Connection,
+        hex: Vec<String>,
+    },
+}
+
+#[derive(Args)]
+struct Connection {
     #[arg(long, default_value = "/dev/ttyUSB1")]
     port: String,
+    /// host:port of a network bridge, used instead of the port.
+    #[arg(long)]
+    net_address: Option<String>,
+}
 
-    #[arg(long, default_value = "4142")]
-    hex_string: String,
+impl Connection {
+    fn open(&self) -> Box<dyn Transport> {
+        let parameters: PortParameters = serde_json::from_value(serde_json::json!({
+            "port_name": self.port,
+            "net_address": self.net_address,
+        }))
+        .unwrap();
+        match open_transport(&parameters) {
+            Ok(transport) => transport,
+            Err(e) => {
+                println!("Failed to open the connection: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
-fn parse_hex_string(in_str: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
-    let mut bytes = Vec::with_capacity(in_str.len() / 2);
-    for i in (0..in_str.len()).step_by(2) {
-        let byte = u8::from_str_radix(&in_str[i..i + 2], 16)?;
-        bytes.push(byte);
+fn parse_board(value: &str) -> Result<BoardKind, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| "easy_touch or intelli_center".to_string())
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
     }
-    Ok(bytes)
+    .map_err(|e| e.to_string())
 }
 
-fn send_to_port(port_name: &str, bytes: &[u8]) {
-    let settings = serial::PortSettings {
-        baud_rate: serial::BaudRate::from_speed(9600),
-        char_size: serial::CharSize::Bits8,
-        parity: serial::Parity::ParityNone,
-        stop_bits: serial::StopBits::Stop1,
-        flow_control: serial::FlowNone,
-    };
+/// Hex digits with any spaces between the bytes.
+fn parse_hex_string(in_str: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = in_str.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in {}", in_str));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|e| format!("{} in {}", e, pair))
+        })
+        .collect()
+}
 
-    let mut port = serial::open(port_name).unwrap();
-    port.configure(&settings).unwrap();
-    port.write_all(bytes).unwrap();
+fn parse_hex_args(hex: &[String]) -> Vec<u8> {
+    match parse_hex_string(&hex.join(" ")) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Error {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_frame(frame: Frame<'_>, board: &dyn Board) {
+    match frame {
+        Frame::Packet(packet) => println!("{}\n{}\n", hex_string(packet), describe(packet, board)),
+        Frame::Chlorinator(packet) => {
+            println!("{}\n{}\n", hex_string(packet), describe_chlorinator(packet))
+        }
+    }
+}
+
+fn decode(bytes: &[u8], board: &dyn Board) {
+    let mut frames = 0;
+    let mut decoder = FrameDecoder::new();
+    decoder.push(bytes, &mut |frame| {
+        frames += 1;
+        print_frame(frame, board);
+    });
+    decoder.idle();
+    if frames == 0 {
+        let counters = decoder.take_counters();
+        if counters.corrupted_packets > 0 {
+            println!("Checksum error");
+        }
+        println!("No complete frame, decoded as a packet without the header and the checksum");
+        println!("{}", describe(bytes, board));
+    }
+}
+
+fn sniff(mut port: Box<dyn Transport>, board: &dyn Board) {
+    if let Err(e) = port.set_read_timeout(IDLE_TIME) {
+        println!("Failed to set the timeout: {}", e);
+        return;
+    }
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = [0; 64];
+    loop {
+        match port.read(&mut buffer) {
+            Ok(0) => {
+                println!("Connection closed");
+                return;
+            }
+            Ok(len) => decoder.push(&buffer[..len], &mut |frame| {
+                println!("{}", Local::now().format("%H:%M:%S%.3f"));
+                print_frame(frame, board);
+            }),
+            Err(e) if is_timeout(&e) => decoder.idle(),
+            Err(e) => {
+                println!("Failed to read: {}", e);
+                return;
+            }
+        }
+        let counters = decoder.take_counters();
+        if counters != Default::default() {
+            println!("Discarded {:?}\n", counters);
+        }
+    }
+}
+
+fn write_bytes(connection: &Connection, bytes: &[u8]) {
+    let mut port = connection.open();
+    if let Err(e) = port.write_all(bytes).and_then(|_| port.flush()) {
+        println!("Failed to write: {}", e);
+    }
 }
 
 fn main() {
     let args = Cli::parse();
-    match parse_hex_string(args.hex_string.as_str()) {
-        Ok(bytes) => send_to_port(args.port.as_str(), &bytes),
-        Err(e) => println!("Error {} parsing {}", e, args.hex_string),
+    match args.command {
+        Command::Decode { hex, board } => decode(&parse_hex_args(&hex), board.board().as_ref()),
+        Command::Sniff { connection, board } => sniff(connection.open(), board.board().as_ref()),
+        Command::Send {
+            connection,
+            dest,
+            source,
+            action,
+            payload,
+            version,
+            dry_run,
+            board,
+        } => {
+            let payload = parse_hex_args(&[payload]);
+            let frame = match PacketBuilder::new(dest, source, action)
+                .protocol_version(version)
+                .payload(&payload)
+            {
+                Ok(builder) => builder.build(),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            println!("{}", hex_string(&frame));
+            decode(&frame, board.board().as_ref());
+            if !dry_run {
+                write_bytes(&connection, &frame);
+            }
+        }
+        Command::Raw { connection, hex } => write_bytes(&connection, &parse_hex_args(&hex)),
     }
 }
@@ -0,0 +1,21 @@
+// A packet with a good checksum but anything in it, as the reader thread hands it over.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pentair_cargo::pool::board::BoardKind;
+use pentair_cargo::pool::command::CommandPolicy;
+use pentair_cargo::pool::protocol::PoolProtocol;
+
+fuzz_target!(|data: &[u8]| {
+    for kind in [BoardKind::EasyTouch, BoardKind::IntelliCenter] {
+        let _ = kind.board().decode_packet(data);
+    }
+
+    // The whole path with the board detection and the replies it queues.
+    let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+    protocol.set_board(None);
+    protocol.process_packet(data);
+    protocol.process_chlorinator_packet(data);
+    while protocol.next_outgoing().is_some() {}
+});
@@ -0,0 +1,37 @@
+// Bytes from the bus in chunks of any size: the decoder must not panic and every frame it
+// yields has to survive encoding and decoding again.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pentair_cargo::pool::frame::{Frame, FrameDecoder};
+use pentair_cargo::pool::message::ProtocolPacket;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte sets the chunk size.
+    let Some((chunk_size, bytes)) = data.split_first() else {
+        return;
+    };
+    let mut packets = Vec::new();
+    let mut decoder = FrameDecoder::new();
+    for chunk in bytes.chunks(*chunk_size as usize + 1) {
+        decoder.push(chunk, &mut |frame| {
+            if let Frame::Packet(packet) = frame {
+                packets.push(packet.to_vec());
+            }
+        });
+    }
+    decoder.idle();
+    decoder.take_counters();
+
+    for packet in packets {
+        let frame = ProtocolPacket::new(&packet).encode().unwrap();
+        let mut decoded = Vec::new();
+        FrameDecoder::new().push(&frame, &mut |frame| {
+            if let Frame::Packet(packet) = frame {
+                decoded.push(packet.to_vec());
+            }
+        });
+        assert_eq!(decoded, vec![packet]);
+    }
+});
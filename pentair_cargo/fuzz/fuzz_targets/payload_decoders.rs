@@ -0,0 +1,25 @@
+// Every payload and packet decoder on its own, the broken input has to be rejected.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pentair_cargo::pool::message::{
+    chlorinator, clock, equipment, heat, intellichem, light, panel_config, pump_state, schedule,
+    system_state,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = panel_config::CircuitDefinition::from_payload(data);
+    let _ = panel_config::decode_custom_name(data);
+    let _ = schedule::Schedule::from_payload(data);
+    let _ = equipment::ValveConfig::from_payload(data);
+    let _ = equipment::SolarConfig::from_payload(data);
+    let _ = light::LightGroup::from_payload(data);
+    let _ = heat::HeatSettings::from_set_payload(data);
+    let _ = chlorinator::ChlorinatorMessage::decode(data);
+    let _ = system_state::SystemState::from_packet(data);
+    let _ = clock::PanelClock::from_packet(data);
+    let _ = heat::HeatSettings::from_packet(data);
+    let _ = pump_state::PumpState::from_packet(data);
+    let _ = intellichem::ChemistryState::from_packet(data);
+});
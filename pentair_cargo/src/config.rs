@@ -15,6 +15,7 @@ pub mod mobile_app;
 pub struct PoolConfig {
     pub comms: config_json::Comms,
     pub port_parameters: config_json::PortParameters,
+    #[serde(default)]
     pub system_parameters: config_json::SystemParameters,
 }
 
@@ -33,7 +34,7 @@ mod tests {
         let config = read_configuration(path::Path::new("config.json")).unwrap();
         assert_eq!(
             config.comms.http_listen_address,
-            Some("127.0.0.1:3000".to_string())
+            Some("0.0.0.0:3000".to_string())
         );
     }
 }
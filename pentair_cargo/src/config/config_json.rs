@@ -1,11 +1,9 @@
+use crate::pool::board::BoardKind;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 // Controller parameters
 
-fn default_enabled() -> bool {
-    true
-}
 fn default_baud_rate() -> usize {
     9600
 }
@@ -23,6 +21,18 @@ fn default_timeout_msec() -> u32 {
     1000
 }
 
+fn default_command_retries() -> u32 {
+    3
+}
+
+fn default_inactivity_retry_sec() -> u32 {
+    10
+}
+
+fn default_local_echo() -> bool {
+    true
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Comms {
     /// The http listen_address
@@ -62,9 +72,16 @@ pub fn decode_stop_bits(stop_bits: u32) -> serial::StopBits {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PortParameters {
-    pub port_name: String,
+    // Serial device with the RS-485 adapter, used when net_address is not set.
+    #[serde(default)]
+    pub port_name: Option<String>,
+    // "host:port" of an RS-485 to Ethernet bridge, as netHost/netPort in nodejs-poolController.
+    pub net_address: Option<String>,
+    // Reconnect if nothing was received for this many seconds, 0 disables it.
+    #[serde(default = "default_inactivity_retry_sec")]
+    pub inactivity_retry_sec: u32,
 
     #[serde(default = "default_baud_rate")]
     pub baud_rate: usize,
@@ -74,26 +91,41 @@ pub struct PortParameters {
     pub parity: String,
     #[serde(default = "default_stop_bits ")]
     pub stop_bits: u32,
+    // How long to wait for a command to be acknowledged.
     #[serde(default = "default_timeout_msec")]
     pub timeout_msec: u32,
-    // If not None, the packages will be saved to this file.
+    // How many times an unacknowledged command is sent again.
+    #[serde(default = "default_command_retries")]
+    pub command_retries: u32,
+    // If not None, the raw bus traffic is recorded to this file, see `--replay`.
     pub samples_file: Option<String>,
+    // The adapter reads back everything we write, it is used to detect collisions.
+    #[serde(default = "default_local_echo")]
+    pub local_echo: bool,
 }
 
-
-fn default_device_id() -> u8 {
-  0x24 
-}
-
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SystemParameters {
     pub sample_file: Option<String>,
 
-    #[serde(default = "default_device_id")]
-    pub controller_id: u8,
+    // Our address on the bus, the default of the board when unset.
+    #[serde(default)]
+    pub controller_id: Option<u8>,
+
+    // "easy_touch" (also IntelliTouch) or "intelli_center", guessed from the traffic when unset.
+    #[serde(default)]
+    pub board: Option<BoardKind>,
 
     // Some devices that have names as "AUX1", it mapped to "Edge Pump"
     #[serde(default)]
     pub device_names: HashMap<String, String>,
 
-}
\ No newline at end of file
+    // If set, we are the chlorinator master and keep asking for this output in percent,
+    // 101 is super chlorination. Leave it unset when the panel controls the chlorinator.
+    #[serde(default)]
+    pub chlorinator_output: Option<u8>,
+
+    // Set the panel clock from the host time when they differ by more than a couple of minutes.
+    #[serde(default)]
+    pub sync_panel_clock: bool,
+}
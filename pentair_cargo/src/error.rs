@@ -0,0 +1,87 @@
+// Errors of the bus and the protocol code. The kind tells the callers what went wrong, the
+// protocol counts the decoding errors by kind.
+
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum PoolError {
+    /// The port or the connection to the bridge failed.
+    Io(io::Error),
+    /// Nothing arrived in time.
+    Timeout,
+    /// The bytes do not make a frame.
+    Framing(&'static str),
+    Checksum,
+    /// A protocol version other than 0x00 and 0x01.
+    UnsupportedProtocol(u8),
+    /// An action we don't decode.
+    UnsupportedAction(u8),
+    /// The payload is shorter than the message needs, names the message.
+    PayloadTooShort(&'static str),
+    /// A value in the payload makes no sense.
+    InvalidPayload(&'static str),
+    /// A payload to send does not fit in a packet, carries its length.
+    PayloadTooLong(usize),
+    /// A command was refused before it was sent, e.g. for an unknown circuit.
+    CommandRejected(String),
+}
+
+impl PoolError {
+    /// Errors after which the port has to be opened again.
+    pub fn is_connection_lost(&self) -> bool {
+        match self {
+            PoolError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::NotFound
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::NotConnected
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Io(e) => write!(f, "I/O error: {}", e),
+            PoolError::Timeout => write!(f, "Timed out"),
+            PoolError::Framing(description) => write!(f, "Framing error: {}", description),
+            PoolError::Checksum => write!(f, "Checksum error"),
+            PoolError::UnsupportedProtocol(version) => {
+                write!(f, "Unsupported protocol version {:#04x}", version)
+            }
+            PoolError::UnsupportedAction(action) => write!(f, "Unsupported action {:#04x}", action),
+            PoolError::PayloadTooShort(message) => write!(f, "{} is too short", message),
+            PoolError::InvalidPayload(description) => write!(f, "Invalid payload: {}", description),
+            PoolError::PayloadTooLong(len) => write!(f, "Payload of {} bytes is too long", len),
+            PoolError::CommandRejected(reason) => write!(f, "Command rejected: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PoolError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PoolError {
+    fn from(e: io::Error) -> Self {
+        PoolError::Io(e)
+    }
+}
+
+// The serial crate maps a missing device to NotFound.
+impl From<serial::Error> for PoolError {
+    fn from(e: serial::Error) -> Self {
+        PoolError::Io(e.into())
+    }
+}
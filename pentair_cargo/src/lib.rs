@@ -0,0 +1,5 @@
+// Protocol and configuration code shared by the service and the debug tools.
+
+pub mod config;
+pub mod error;
+pub mod pool;
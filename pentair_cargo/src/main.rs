@@ -6,12 +6,11 @@ use std::fs::File;
 use std::path::PathBuf;
 use std::sync::RwLock;
 use std::thread;
+use std::time::Duration;
 use tower_http::services::ServeDir;
 
-// A thread/
+use pentair_cargo::{config, pool};
 
-mod config;
-mod pool;
 mod ui;
 
 // Command line arguments
@@ -25,6 +24,14 @@ struct Cli {
 
     #[arg(short, long, default_value = "true")]
     logtostderr: bool,
+
+    /// Instead of the port, feed the bus traffic recorded in this capture file.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Replay speed, 1 is real time, 0 is as fast as possible.
+    #[arg(long, default_value = "1")]
+    replay_speed: f64,
 }
 
 fn init_logging(verbosity: u8, logtostderr: bool) {
@@ -64,15 +71,35 @@ fn main() {
     let config = config::read_configuration(&args.config).expect("Failed to read configuration");
     trace!("Configuration loaded: {:?}", config);
 
-    let pool_protocol = pool::PoolProtocolRW::new(RwLock::new(pool::protocol::PoolProtocol::new()));
-    let port =
-        pool::serial::serial_port(&config.port_parameters).expect("Failed to open serial port");
-    {
+    let command_policy = pool::command::CommandPolicy {
+        retries: config.port_parameters.command_retries,
+        timeout: Duration::from_millis(config.port_parameters.timeout_msec as u64),
+    };
+    let controller_id = config.system_parameters.controller_id;
+    let mut protocol =
+        pool::protocol::PoolProtocol::new(controller_id.unwrap_or_default(), command_policy);
+    protocol.set_board(config.system_parameters.board);
+    if controller_id.is_none() {
+        protocol.use_board_controller_id();
+    }
+    if let Some(output) = config.system_parameters.chlorinator_output {
+        protocol.set_chlorinator_output(output);
+    }
+    protocol.set_clock_sync(config.system_parameters.sync_panel_clock);
+    protocol.set_device_names(config.system_parameters.device_names.clone());
+    let pool_protocol = pool::PoolProtocolRW::new(RwLock::new(protocol));
+    if let Some(replay) = args.replay {
         let p1 = pool_protocol.clone();
-        thread::spawn(move || pool::serial::port_read_thread(port, p1));
+        let speed = args.replay_speed;
+        thread::spawn(move || pool::capture::replay_thread(&replay, speed, p1));
+    } else {
+        pool_protocol.write().unwrap().request_configuration();
+        let p1 = pool_protocol.clone();
+        let port_parameters = config.port_parameters.clone();
+        thread::spawn(move || pool::serial::port_read_thread(port_parameters, p1));
     }
 
-    trace!("Serial port opened");
+    trace!("Port thread started");
     match run_server(&config.comms, pool_protocol) {
         Ok(()) => info!("Successfully stopping"),
         Err(e) => error!("Failed {}", e),
@@ -95,10 +122,27 @@ pub async fn run_server(
         .route("/control", post(ui::control_command))
         .route("/state", get(ui::state_json))
         .route("/log", get(ui::log_json))
+        .route("/config", get(ui::panel_config))
+        .route("/config/equipment", get(ui::equipment_config))
+        .route("/heat", post(ui::heat_command))
+        .route("/clock", post(ui::set_clock))
+        .route("/lights", get(ui::light_state).post(ui::light_command))
+        .route("/lights/group", post(ui::light_group_command))
+        .route(
+            "/schedules",
+            get(ui::list_schedules).post(ui::create_schedule),
+        )
+        .route(
+            "/schedules/{id}",
+            get(ui::get_schedule)
+                .put(ui::update_schedule)
+                .delete(ui::delete_schedule),
+        )
+        .route("/pump/{id}", get(ui::pump_state).post(ui::pump_command))
         .route("/ws", any(ui::ws_handler))
         .with_state(pool_protocol)
         .nest_service("/assets", ServeDir::new("assets"));
-    if config.https_listen_address.is_some() {
+    if let Some(https_listen_address) = &config.https_listen_address {
         if config.cert_path.is_none() || config.key_path.is_none() {
             panic!("Missing cert_path or key_path");
         }
@@ -107,12 +151,7 @@ pub async fn run_server(
             config.key_path.as_ref().unwrap(),
         )
         .await?;
-        let addr = config
-            .https_listen_address
-            .as_ref()
-            .unwrap()
-            .parse()
-            .expect("Invalid https address");
+        let addr = https_listen_address.parse().expect("Invalid https address");
         axum_server::tls_rustls::bind_rustls(addr, rustls_config)
             .serve(app.into_make_service())
             .await
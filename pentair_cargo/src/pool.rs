@@ -1,6 +1,11 @@
+pub mod board;
+pub mod capture;
+pub mod command;
+pub mod frame;
+pub mod message;
 pub mod protocol;
 pub mod serial;
-pub mod message;
+pub mod transport;
 use std::sync::{Arc, RwLock};
 
 pub type PoolProtocolRW = Arc<RwLock<protocol::PoolProtocol>>;
@@ -0,0 +1,443 @@
+// Panel families: EasyTouch/IntelliTouch and IntelliCenter share the framing but differ in
+// the status layout, the circuit numbering and the commands they take.
+
+use crate::error::PoolError;
+use crate::pool::message::circuit::{Circuit, CircuitFunction};
+use crate::pool::message::system_state::{StatusLayout, SystemState, EASY_TOUCH_LAYOUT};
+use crate::pool::message::{
+    self, clock, equipment, heat, light, panel_config, schedule, PacketType, ProtocolPacket,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoardKind {
+    #[serde(alias = "intelli_touch")]
+    EasyTouch,
+    IntelliCenter,
+}
+
+impl BoardKind {
+    pub fn board(&self) -> Box<dyn Board> {
+        match self {
+            BoardKind::EasyTouch => Box::new(EasyTouch),
+            BoardKind::IntelliCenter => Box::new(IntelliCenter),
+        }
+    }
+}
+
+/// What the protocol needs to know about the panel it talks to.
+pub trait Board: Send + Sync {
+    fn kind(&self) -> BoardKind;
+
+    /// Our address on the bus when the configuration does not set one.
+    fn default_controller_id(&self) -> u8;
+
+    fn status_layout(&self) -> &'static StatusLayout;
+
+    /// Decodes the actions of the panel and the controllers, the header has been checked.
+    fn decode_action(&self, packet: &ProtocolPacket) -> Result<PacketType, PoolError>;
+
+    /// The header, then the payload: the pumps and IntelliChem talk the same with every
+    /// panel, the rest is up to the board.
+    fn decode_packet(&self, packet: &[u8]) -> Result<ProtocolPacket, PoolError> {
+        let mut decoded = ProtocolPacket::decode_header(packet)?;
+        decoded.decoded = match decoded.decode_device_payload()? {
+            Some(device) => device,
+            None => self.decode_action(&decoded)?,
+        };
+        Ok(decoded)
+    }
+
+    /// The action of the circuit change, the panel acknowledges it with the same one.
+    fn circuit_change_action(&self) -> u8;
+
+    /// Payload that turns a circuit on or off, the other circuits are as they are now.
+    fn encode_circuit_change(&self, circuits: &[Circuit], number: u8, on: bool) -> Vec<u8>;
+
+    /// What we ask the panel at start: the request, the action of the answer and the
+    /// argument.
+    fn configuration_requests(&self) -> Vec<(u8, u8, u8)>;
+
+    /// The layout is not checked against traffic of a real panel yet: the status is read,
+    /// but no commands are sent to the panel.
+    fn is_experimental(&self) -> bool {
+        false
+    }
+}
+
+/// Guesses the panel from a packet it sent, None when the packet tells nothing.
+pub fn detect_board(packet: &[u8]) -> Option<BoardKind> {
+    let packet = ProtocolPacket::new(packet);
+    if packet.get_protocol_version() != 0x01 || packet.get_source() != message::PANEL_ADDRESS {
+        return None;
+    }
+    match packet.get_action() {
+        // Only EasyTouch broadcasts its clock and the heat status.
+        clock::CLOCK_ACTION | heat::HEAT_STATUS_ACTION => Some(BoardKind::EasyTouch),
+        IC_CONFIG_ACTION | IC_EXTENDED_STATUS_ACTION | IC_VERSION_ACTION => {
+            Some(BoardKind::IntelliCenter)
+        }
+        _ => None,
+    }
+}
+
+pub struct EasyTouch;
+
+impl Board for EasyTouch {
+    fn kind(&self) -> BoardKind {
+        BoardKind::EasyTouch
+    }
+
+    fn default_controller_id(&self) -> u8 {
+        0x24
+    }
+
+    fn status_layout(&self) -> &'static StatusLayout {
+        &EASY_TOUCH_LAYOUT
+    }
+
+    fn decode_action(&self, packet: &ProtocolPacket) -> Result<PacketType, PoolError> {
+        let bytes = packet.get_bytes();
+        let payload = packet.get_payload();
+        let from_panel = packet.get_source() == message::PANEL_ADDRESS;
+        let broadcast = from_panel && packet.get_destination() == message::BROADCAST_ADDRESS;
+        let action = packet.get_action();
+        Ok(match action {
+            message::STATUS_ACTION if broadcast => {
+                PacketType::Status(SystemState::from_packet(bytes)?)
+            }
+            message::SET_CIRCUIT_ACTION => match payload {
+                [circuit, state, ..] => PacketType::CircuitStatusChange {
+                    circuit: *circuit,
+                    state: *state != 0,
+                },
+                _ => PacketType::Unknown,
+            },
+            message::ACK_ACTION => {
+                PacketType::CircuitStatusResponse(payload.first().copied().unwrap_or_default())
+            }
+            panel_config::REMOTE_LAYOUT_REQUEST => PacketType::RemoteLayoutRequest,
+            panel_config::REMOTE_LAYOUT_ACTION => {
+                PacketType::RemoteLayoutResponse(payload.to_vec())
+            }
+            heat::HEAT_STATUS_REQUEST
+            | panel_config::CUSTOM_NAME_REQUEST
+            | panel_config::CIRCUIT_DEFINITION_REQUEST
+            | schedule::SCHEDULE_REQUEST
+            | equipment::VALVE_CONFIG_REQUEST
+            | equipment::SOLAR_CONFIG_REQUEST
+            | light::LIGHT_GROUP_REQUEST => PacketType::ConfigRequest(action),
+            schedule::SCHEDULE_ACTION if from_panel => {
+                match schedule::Schedule::from_payload(payload) {
+                    Some((id, schedule)) => PacketType::Schedule { id, schedule },
+                    None => PacketType::Unknown,
+                }
+            }
+            schedule::SET_SCHEDULE_ACTION => match schedule::Schedule::from_payload(payload) {
+                Some((id, schedule)) => PacketType::SetSchedule { id, schedule },
+                None => PacketType::Unknown,
+            },
+            panel_config::CIRCUIT_DEFINITION_ACTION if from_panel => {
+                match panel_config::CircuitDefinition::from_payload(payload) {
+                    Some(definition) => PacketType::CircuitDefinition(definition),
+                    None => PacketType::Unknown,
+                }
+            }
+            panel_config::CUSTOM_NAME_ACTION if from_panel => {
+                match panel_config::decode_custom_name(payload) {
+                    Some((index, name)) => PacketType::CustomName { index, name },
+                    None => PacketType::Unknown,
+                }
+            }
+            equipment::VALVE_CONFIG_ACTION if from_panel => {
+                match equipment::ValveConfig::from_payload(payload) {
+                    Some(config) => PacketType::ValveConfig(config),
+                    None => PacketType::Unknown,
+                }
+            }
+            equipment::SET_VALVE_CONFIG_ACTION => {
+                match equipment::ValveConfig::from_payload(payload) {
+                    Some(config) => PacketType::SetValveConfig(config),
+                    None => PacketType::Unknown,
+                }
+            }
+            equipment::SOLAR_CONFIG_ACTION if from_panel => {
+                match equipment::SolarConfig::from_payload(payload) {
+                    Some(config) => PacketType::SolarConfig(config),
+                    None => PacketType::Unknown,
+                }
+            }
+            equipment::SET_SOLAR_CONFIG_ACTION => {
+                match equipment::SolarConfig::from_payload(payload) {
+                    Some(config) => PacketType::SetSolarConfig(config),
+                    None => PacketType::Unknown,
+                }
+            }
+            light::LIGHT_COMMAND_ACTION => match payload
+                .first()
+                .copied()
+                .and_then(light::LightTheme::from_code)
+            {
+                Some(theme) => PacketType::LightCommand(theme),
+                None => PacketType::Unknown,
+            },
+            light::LIGHT_GROUP_ACTION if from_panel => {
+                match light::LightGroup::from_payload(payload) {
+                    Some(group) => PacketType::LightGroup(group),
+                    None => PacketType::Unknown,
+                }
+            }
+            light::SET_LIGHT_GROUP_ACTION => match light::LightGroup::from_payload(payload) {
+                Some(group) => PacketType::SetLightGroup(group),
+                None => PacketType::Unknown,
+            },
+            clock::CLOCK_ACTION if from_panel => {
+                PacketType::ClockBroadcast(clock::PanelClock::from_packet(bytes)?)
+            }
+            clock::SET_CLOCK_ACTION => PacketType::SetClock(clock::PanelClock::from_packet(bytes)?),
+            heat::HEAT_STATUS_ACTION if from_panel => {
+                PacketType::HeatStatus(heat::HeatSettings::from_packet(bytes)?)
+            }
+            heat::SET_HEAT_ACTION => match heat::HeatSettings::from_set_payload(payload) {
+                Some(settings) => PacketType::SetHeat(settings),
+                None => PacketType::Unknown,
+            },
+            _ => PacketType::Unknown,
+        })
+    }
+
+    fn circuit_change_action(&self) -> u8 {
+        message::SET_CIRCUIT_ACTION
+    }
+
+    fn encode_circuit_change(&self, _circuits: &[Circuit], number: u8, on: bool) -> Vec<u8> {
+        vec![number, on as u8]
+    }
+
+    fn configuration_requests(&self) -> Vec<(u8, u8, u8)> {
+        // The heat settings first, set heat needs them.
+        let mut requests = vec![(heat::HEAT_STATUS_REQUEST, heat::HEAT_STATUS_ACTION, 0)];
+        for circuit in 1..=panel_config::MAX_CIRCUITS {
+            requests.push((
+                panel_config::CIRCUIT_DEFINITION_REQUEST,
+                panel_config::CIRCUIT_DEFINITION_ACTION,
+                circuit,
+            ));
+        }
+        for index in 0..panel_config::MAX_CUSTOM_NAMES {
+            requests.push((
+                panel_config::CUSTOM_NAME_REQUEST,
+                panel_config::CUSTOM_NAME_ACTION,
+                index,
+            ));
+        }
+        requests.push((
+            panel_config::REMOTE_LAYOUT_REQUEST,
+            panel_config::REMOTE_LAYOUT_ACTION,
+            0,
+        ));
+        for id in 1..=schedule::MAX_SCHEDULES {
+            requests.push((schedule::SCHEDULE_REQUEST, schedule::SCHEDULE_ACTION, id));
+        }
+        requests.push((
+            equipment::VALVE_CONFIG_REQUEST,
+            equipment::VALVE_CONFIG_ACTION,
+            0,
+        ));
+        requests.push((
+            equipment::SOLAR_CONFIG_REQUEST,
+            equipment::SOLAR_CONFIG_ACTION,
+            0,
+        ));
+        requests.push((light::LIGHT_GROUP_REQUEST, light::LIGHT_GROUP_ACTION, 0));
+        requests
+    }
+}
+
+// IntelliCenter has up to 40 circuits in the status and features from 129 on. Offsets are in
+// the packet without the header. None of the offsets below come from a capture of a real
+// IntelliCenter, the board stays experimental until they are checked against one.
+const IC_MASK_IDX: usize = 7;
+const IC_MASK_BYTES: usize = 5;
+const IC_FEATURE_MASK_IDX: usize = 14;
+const IC_FEATURE_MASK_BYTES: usize = 4;
+const IC_FIRST_FEATURE: u8 = 129;
+const IC_LAST_CIRCUIT: u8 = (IC_MASK_BYTES * 8) as u8;
+/// Circuit changes go in the external configuration message, section 15 has the state of
+/// every circuit: masks of the circuits from payload byte 3 and of the features from 9.
+pub const IC_SET_CIRCUITS_ACTION: u8 = 0xA8;
+/// Configuration items, asked for one by one.
+pub const IC_CONFIG_ACTION: u8 = 0x1E;
+pub const IC_CONFIG_REQUEST: u8 = 0xDE;
+pub const IC_EXTENDED_STATUS_ACTION: u8 = 0xCC;
+pub const IC_VERSION_ACTION: u8 = 0xA4;
+const IC_CIRCUIT_STATE_SECTION: u8 = 15;
+const IC_SET_MASK_IDX: usize = 3;
+const IC_SET_FEATURE_MASK_IDX: usize = 9;
+const IC_SET_CIRCUITS_PAYLOAD_LEN: usize = 40;
+
+fn intellicenter_circuit(number: u8) -> Circuit {
+    let (name, function) = match number {
+        1 => ("spa".to_string(), CircuitFunction::Spa),
+        2..=5 => (format!("aux{}", number - 1), CircuitFunction::Generic),
+        6 => ("pool".to_string(), CircuitFunction::Pool),
+        7..=IC_LAST_CIRCUIT => (format!("aux{}", number - 2), CircuitFunction::Generic),
+        IC_FIRST_FEATURE.. => (
+            format!("feature{}", number - IC_FIRST_FEATURE + 1),
+            CircuitFunction::Generic,
+        ),
+        _ => (format!("circuit{}", number), CircuitFunction::Generic),
+    };
+    Circuit {
+        id: name.clone(),
+        name,
+        function,
+        ..Circuit::new(number)
+    }
+}
+
+fn intellicenter_defaults() -> Vec<Circuit> {
+    (1..=6).map(intellicenter_circuit).collect()
+}
+
+/// Status of IntelliCenter, the units and the heat status are not in it.
+pub const INTELLI_CENTER_LAYOUT: StatusLayout = StatusLayout {
+    mask_idx: IC_MASK_IDX,
+    mask_bytes: IC_MASK_BYTES,
+    feature_mask: Some((IC_FEATURE_MASK_IDX, IC_FEATURE_MASK_BYTES, IC_FIRST_FEATURE)),
+    units_idx: None,
+    heat_status_idx: None,
+    water_temp_idx: 19,
+    air_temp_idx: 23,
+    solar_temp_idx: 24,
+    default_circuits: intellicenter_defaults,
+    new_circuit: intellicenter_circuit,
+};
+
+pub struct IntelliCenter;
+
+impl Board for IntelliCenter {
+    fn kind(&self) -> BoardKind {
+        BoardKind::IntelliCenter
+    }
+
+    // IntelliCenter answers the addresses of wireless and indoor panels only.
+    fn default_controller_id(&self) -> u8 {
+        0x21
+    }
+
+    fn status_layout(&self) -> &'static StatusLayout {
+        &INTELLI_CENTER_LAYOUT
+    }
+
+    // Only the status and the acknowledgements, the configuration items are not decoded.
+    fn decode_action(&self, packet: &ProtocolPacket) -> Result<PacketType, PoolError> {
+        let broadcast = packet.get_source() == message::PANEL_ADDRESS
+            && packet.get_destination() == message::BROADCAST_ADDRESS;
+        let payload = packet.get_payload();
+        Ok(match packet.get_action() {
+            message::STATUS_ACTION if broadcast => PacketType::Status(SystemState::from_status(
+                packet.get_bytes(),
+                &INTELLI_CENTER_LAYOUT,
+            )?),
+            message::ACK_ACTION => {
+                PacketType::CircuitStatusResponse(payload.first().copied().unwrap_or_default())
+            }
+            _ => PacketType::Unknown,
+        })
+    }
+
+    fn circuit_change_action(&self) -> u8 {
+        IC_SET_CIRCUITS_ACTION
+    }
+
+    fn encode_circuit_change(&self, circuits: &[Circuit], number: u8, on: bool) -> Vec<u8> {
+        let mut payload = vec![0; IC_SET_CIRCUITS_PAYLOAD_LEN];
+        payload[0] = IC_CIRCUIT_STATE_SECTION;
+        for circuit in circuits {
+            let circuit_on = if circuit.number == number {
+                on
+            } else {
+                circuit.on
+            };
+            let bit = match circuit.number {
+                IC_FIRST_FEATURE.. => {
+                    IC_SET_FEATURE_MASK_IDX * 8 + (circuit.number - IC_FIRST_FEATURE) as usize
+                }
+                number => IC_SET_MASK_IDX * 8 + number as usize - 1,
+            };
+            if circuit_on && bit < IC_SET_CIRCUITS_PAYLOAD_LEN * 8 {
+                payload[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        payload
+    }
+
+    // The configuration items of IntelliCenter (0xDE asks, 0x1E answers) are not decoded,
+    // the circuits keep their default names.
+    fn configuration_requests(&self) -> Vec<(u8, u8, u8)> {
+        Vec::new()
+    }
+
+    fn is_experimental(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_intellicenter() {
+    let board = BoardKind::IntelliCenter.board();
+    // Nothing here is captured from a panel, the packets only follow the offsets above.
+    assert!(board.is_experimental());
+    assert!(!BoardKind::EasyTouch.board().is_experimental());
+    // Pool and feature 2 on, water at 80 and air at 70.
+    let mut packet = vec![0x01, 0x0F, 0x10, 0x02, 0x1D];
+    packet.resize(5 + 0x1D, 0);
+    packet[IC_MASK_IDX] = 0x20;
+    packet[IC_FEATURE_MASK_IDX] = 0x02;
+    packet[19] = 80;
+    packet[23] = 70;
+    let decoded = board.decode_packet(&packet).unwrap();
+    let PacketType::Status(state) = decoded.decoded else {
+        panic!("Not a status {:?}", decoded.decoded);
+    };
+    let on: Vec<_> = state
+        .get_circuits()
+        .into_iter()
+        .filter(|circuit| circuit.on)
+        .map(|circuit| circuit.name)
+        .collect();
+    assert_eq!(on, vec!["pool", "feature2"]);
+    assert_eq!(state.get_temperatures()[0].1, 80.0);
+
+    // Turning the spa on keeps the pool and the feature on.
+    let payload = board.encode_circuit_change(&state.get_circuits(), 1, true);
+    assert_eq!(payload[..4], [IC_CIRCUIT_STATE_SECTION, 0x00, 0x00, 0x21]);
+    assert_eq!(payload[IC_SET_FEATURE_MASK_IDX], 0x02);
+
+    // The clock broadcast of EasyTouch is not taken from IntelliCenter.
+    let clock = [
+        0x01, 0x0F, 0x10, 0x05, 0x08, 0x09, 0x29, 0x01, 0x12, 0x0A, 0x1A, 0x00, 0x01,
+    ];
+    assert!(matches!(
+        board.decode_packet(&clock).unwrap().decoded,
+        PacketType::Unknown
+    ));
+    // Pumps answer the same to every panel.
+    let pump = [
+        0x00, 0x10, 0x60, 0x07, 0x0F, 0x0A, 0x00, 0x02, 0x04, 0x1A, 0x09, 0x92, 0x2A, 0x00, 0x00,
+        0x00, 0x00, 0x05, 0x0E, 0x21,
+    ];
+    assert!(matches!(
+        board.decode_packet(&pump).unwrap().decoded,
+        PacketType::PumpStatus(_)
+    ));
+    assert_eq!(detect_board(&clock), Some(BoardKind::EasyTouch));
+    assert_eq!(
+        detect_board(&[0x01, 0x0F, 0x10, 0xCC, 0x01, 0x00]),
+        Some(BoardKind::IntelliCenter)
+    );
+    assert_eq!(detect_board(&packet), None);
+}
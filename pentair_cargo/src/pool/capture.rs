@@ -0,0 +1,310 @@
+// Recording of the raw bus traffic and its replay, to reproduce problems from the field.
+//
+// The capture is a JSON lines file, one record per chunk of bytes:
+// {"timestamp":"2026-10-18T09:15:02.120+02:00","direction":"rx","bytes":"ff00ffa5..."}
+// Received bytes are grouped until the bus goes idle or we transmit.
+
+use crate::pool::serial::run_connection;
+use crate::pool::transport::{is_timeout, MemoryTransport, Transport};
+use crate::pool::PoolProtocolRW;
+use chrono::{DateTime, Local};
+use log::{error, info};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Received from the bus.
+    Rx,
+    /// Written by us.
+    Tx,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub timestamp: DateTime<Local>,
+    pub direction: Direction,
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub bytes: Vec<u8>,
+}
+
+fn to_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    serializer.serialize_str(&hex)
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if !hex.len().is_multiple_of(2) {
+        return Err(serde::de::Error::custom("Odd number of hex digits"));
+    }
+    // By bytes, the line may have anything in it.
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digit = |b: u8| (b as char).to_digit(16);
+            match (digit(pair[0]), digit(pair[1])) {
+                (Some(high), Some(low)) => Ok((high << 4 | low) as u8),
+                _ => Err(serde::de::Error::custom("Invalid hex digit")),
+            }
+        })
+        .collect()
+}
+
+/// Appends records to a capture file.
+pub struct CaptureWriter {
+    writer: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(CaptureWriter {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Reads all the records from a capture file.
+pub fn read_capture(path: &Path) -> io::Result<Vec<CaptureRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok(records)
+}
+
+/// Passes everything through to the inner transport and records it.
+pub struct CaptureTransport<'a, T: Transport> {
+    inner: T,
+    writer: &'a mut CaptureWriter,
+    received: Vec<u8>,
+    received_at: DateTime<Local>,
+}
+
+impl<'a, T: Transport> CaptureTransport<'a, T> {
+    pub fn new(inner: T, writer: &'a mut CaptureWriter) -> Self {
+        CaptureTransport {
+            inner,
+            writer,
+            received: Vec::new(),
+            received_at: Local::now(),
+        }
+    }
+
+    fn flush_received(&mut self) {
+        if self.received.is_empty() {
+            return;
+        }
+        let record = CaptureRecord {
+            timestamp: self.received_at,
+            direction: Direction::Rx,
+            bytes: std::mem::take(&mut self.received),
+        };
+        if let Err(e) = self.writer.write(&record) {
+            error!("Failed to write the capture: {}", e);
+        }
+    }
+}
+
+impl<T: Transport> Read for CaptureTransport<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(len) => {
+                if self.received.is_empty() {
+                    self.received_at = Local::now();
+                }
+                self.received.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+            Err(e) => {
+                if is_timeout(&e) {
+                    self.flush_received();
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<T: Transport> Write for CaptureTransport<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_received();
+        let len = self.inner.write(buf)?;
+        let record = CaptureRecord {
+            timestamp: Local::now(),
+            direction: Direction::Tx,
+            bytes: buf[..len].to_vec(),
+        };
+        if let Err(e) = self.writer.write(&record) {
+            error!("Failed to write the capture: {}", e);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Transport> Transport for CaptureTransport<'_, T> {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+/// Writes the received bytes of the capture to the port, keeping the original gaps divided
+/// by `speed`. Zero speed sends everything without waiting.
+pub fn feed_capture<W: Write>(
+    records: &[CaptureRecord],
+    port: &mut W,
+    speed: f64,
+) -> io::Result<()> {
+    let mut previous: Option<DateTime<Local>> = None;
+    for record in records.iter().filter(|r| r.direction == Direction::Rx) {
+        if let Some(previous) = previous {
+            let gap = (record.timestamp - previous).to_std().unwrap_or_default();
+            if speed > 0. {
+                thread::sleep(gap.div_f64(speed));
+            }
+        }
+        previous = Some(record.timestamp);
+        port.write_all(&record.bytes)?;
+    }
+    Ok(())
+}
+
+/// Replaces the port thread: runs the packet reader over the received bytes of a capture.
+pub fn replay_thread(path: &Path, speed: f64, pool_protocol: PoolProtocolRW) {
+    let records = match read_capture(path) {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to read the capture {:?}: {}", path, e);
+            return;
+        }
+    };
+    info!("Replaying {} records from {:?}", records.len(), path);
+    let (mut bus, port) = MemoryTransport::pipe();
+    thread::spawn(move || {
+        // The reader sees a silent bus after the end of the capture.
+        match feed_capture(&records, &mut bus, speed) {
+            Ok(()) => info!("Replay finished"),
+            Err(e) => error!("Replay failed: {}", e),
+        }
+    });
+    let e = run_connection(port, &pool_protocol, false, None);
+    error!("Replay stopped: {}", e);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::command::CommandPolicy;
+    use crate::pool::protocol::PoolProtocol;
+    use std::sync::{Arc, RwLock};
+    use std::time::Instant;
+
+    fn temp_capture(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let path = temp_capture("capture_round_trip");
+        let (mut bus, port) = MemoryTransport::pipe();
+        {
+            let mut writer = CaptureWriter::create(&path).unwrap();
+            let mut port = CaptureTransport::new(port, &mut writer);
+            port.set_read_timeout(Duration::from_millis(10)).unwrap();
+            bus.write_all(&[0xFF, 0x00, 0xFF]).unwrap();
+            let mut buf = [0; 8];
+            assert_eq!(port.read(&mut buf).unwrap(), 3);
+            assert!(port.read(&mut buf).is_err());
+            port.write_all(&[0xA5, 0x01]).unwrap();
+        }
+
+        let records = read_capture(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Rx);
+        assert_eq!(records[0].bytes, vec![0xFF, 0x00, 0xFF]);
+        assert_eq!(records[1].direction, Direction::Tx);
+        assert_eq!(records[1].bytes, vec![0xA5, 0x01]);
+        std::fs::remove_file(&path).unwrap();
+
+        // A broken line is an error, not a panic.
+        for bytes in ["aéb", "aéb0", "0g"] {
+            let line = format!(
+                r#"{{"timestamp":"2026-10-18T09:15:02.120+02:00","direction":"rx","bytes":"{}"}}"#,
+                bytes
+            );
+            assert!(serde_json::from_str::<CaptureRecord>(&line).is_err());
+        }
+    }
+
+    #[test]
+    fn test_replay_updates_state() {
+        let path = temp_capture("capture_replay");
+        {
+            let mut writer = CaptureWriter::create(&path).unwrap();
+            let mut frame = vec![0xFF, 0x00, 0xFF, 0xA5];
+            let packet = [
+                0x01, 0x0F, 0x10, 0x02, 0x1D, 0x0D, 0x1D, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x52, 0x52, 0x00, 0x00, 0x48, 0x53, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ];
+            frame.extend_from_slice(&packet);
+            let checksum: u16 = 0xA5 + packet.iter().map(|b| *b as u16).sum::<u16>();
+            frame.extend_from_slice(&checksum.to_be_bytes());
+            writer
+                .write(&CaptureRecord {
+                    timestamp: Local::now(),
+                    direction: Direction::Rx,
+                    bytes: frame,
+                })
+                .unwrap();
+        }
+
+        let pool_protocol = Arc::new(RwLock::new(PoolProtocol::new(
+            0x24,
+            CommandPolicy::default(),
+        )));
+        let p1 = pool_protocol.clone();
+        let replay_path = path.clone();
+        thread::spawn(move || replay_thread(&replay_path, 0., p1));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool_protocol
+            .read()
+            .unwrap()
+            .get_recent_packets()
+            .is_empty()
+        {
+            assert!(
+                Instant::now() < deadline,
+                "Replay did not reach the protocol"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        let state = pool_protocol.read().unwrap().get_state();
+        assert_eq!(state.get_temperatures()[0], ("water".to_string(), 82.));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
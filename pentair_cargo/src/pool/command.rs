@@ -0,0 +1,376 @@
+// Tracking of the commands we send to the bus until they are acknowledged.
+
+use log::{debug, warn};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+pub type CommandId = u32;
+
+/// Where a command is in its life.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum CommandState {
+    /// Waiting for the bus to become idle.
+    Queued,
+    /// Written to the bus, waiting for the acknowledgement.
+    Sent,
+    /// The device confirmed the command.
+    Acked,
+    /// No acknowledgement in time, queued again.
+    Retried,
+    /// Gave up on the command.
+    Failed,
+}
+
+/// The final result reported to whoever queued the command.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum CommandOutcome {
+    Acked,
+    /// Not acknowledged after all the retries, or never got to the bus in that time.
+    TimedOut,
+    /// Could not be written to the bus, or the connection was lost.
+    Failed,
+}
+
+/// How hard we try to get a command acknowledged.
+#[derive(Clone, Copy, Debug)]
+pub struct CommandPolicy {
+    /// Number of times the command is sent again after a timeout.
+    pub retries: u32,
+    /// How long to wait for the acknowledgement after sending.
+    pub timeout: Duration,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        CommandPolicy {
+            retries: 3,
+            timeout: Duration::from_millis(1000),
+        }
+    }
+}
+
+/// Handle returned to whoever queued a command.
+pub struct CommandHandle {
+    pub id: CommandId,
+    pub outcome: oneshot::Receiver<CommandOutcome>,
+}
+
+struct Command {
+    id: CommandId,
+    frame: Vec<u8>,
+    // Device and action code of the acknowledgement that confirms this command, and the
+    // first payload byte of it when several requests get the same answer.
+    ack_source: u8,
+    ack_action: u8,
+    ack_argument: Option<u8>,
+    state: CommandState,
+    // Times the command was sent to the bus.
+    attempts: u32,
+    queued_at: Instant,
+    sent_at: Option<Instant>,
+    reply: Option<oneshot::Sender<CommandOutcome>>,
+}
+
+impl Command {
+    fn finish(&mut self, state: CommandState, outcome: CommandOutcome) {
+        self.state = state;
+        if let Some(reply) = self.reply.take() {
+            // Nobody may be waiting for the result anymore, it is fine.
+            let _ = reply.send(outcome);
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.state, CommandState::Acked | CommandState::Failed)
+    }
+}
+
+/// Keeps the commands in flight and a few finished ones for inspection.
+pub struct CommandTracker {
+    policy: CommandPolicy,
+    next_id: CommandId,
+    commands: Vec<Command>,
+}
+
+/// Finished commands kept around so their state can be queried.
+const FINISHED_COMMANDS_KEPT: usize = 10;
+
+impl CommandTracker {
+    pub fn new(policy: CommandPolicy) -> Self {
+        CommandTracker {
+            policy,
+            next_id: 1,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Registers a new command, the caller is responsible for queueing the frame. With
+    /// `ack_argument` only the answer starting with it confirms the command.
+    pub fn add(
+        &mut self,
+        frame: Vec<u8>,
+        ack_source: u8,
+        ack_action: u8,
+        ack_argument: Option<u8>,
+    ) -> CommandHandle {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let (reply, outcome) = oneshot::channel();
+        self.commands.push(Command {
+            id,
+            frame,
+            ack_source,
+            ack_action,
+            ack_argument,
+            state: CommandState::Queued,
+            attempts: 0,
+            queued_at: Instant::now(),
+            sent_at: None,
+            reply: Some(reply),
+        });
+        self.cleanup();
+        CommandHandle { id, outcome }
+    }
+
+    pub fn state(&self, id: CommandId) -> Option<CommandState> {
+        self.commands.iter().find(|c| c.id == id).map(|c| c.state)
+    }
+
+    /// True if there is a command with this acknowledgement action that is not finished.
+    pub fn is_pending(&self, ack_action: u8) -> bool {
+        self.commands
+            .iter()
+            .any(|c| c.ack_action == ack_action && !c.is_finished())
+    }
+
+    /// The frame went out to the bus.
+    pub fn sent(&mut self, id: CommandId, now: Instant) {
+        if let Some(command) = self.find_active(id) {
+            command.state = CommandState::Sent;
+            command.attempts += 1;
+            command.sent_at = Some(now);
+        }
+    }
+
+    /// The frame could not be written to the bus.
+    pub fn transmit_failed(&mut self, id: CommandId) {
+        if let Some(command) = self.find_active(id) {
+            warn!("Command {} could not be sent", id);
+            command.finish(CommandState::Failed, CommandOutcome::Failed);
+        }
+    }
+
+    /// An acknowledgement arrived, it confirms the oldest command waiting for it. `argument`
+    /// is the first byte of its payload. A late answer to a command queued again also
+    /// confirms it, the caller drops the queued frame.
+    pub fn acknowledged(
+        &mut self,
+        ack_source: u8,
+        ack_action: u8,
+        argument: Option<u8>,
+    ) -> Option<CommandId> {
+        let command = self.commands.iter_mut().find(|c| {
+            matches!(c.state, CommandState::Sent | CommandState::Retried)
+                && c.ack_source == ack_source
+                && c.ack_action == ack_action
+                && c.ack_argument
+                    .is_none_or(|ack_argument| argument == Some(ack_argument))
+        })?;
+        debug!("Command {} acknowledged", command.id);
+        command.finish(CommandState::Acked, CommandOutcome::Acked);
+        Some(command.id)
+    }
+
+    /// Gives up on the commands waiting for this acknowledgement, returns them so that their
+    /// frames can be dropped from the queue.
+    pub fn cancel(&mut self, ack_action: u8) -> Vec<CommandId> {
+        let mut cancelled = Vec::new();
+        for command in self
+            .commands
+            .iter_mut()
+            .filter(|c| c.ack_action == ack_action && !c.is_finished())
+        {
+            debug!("Command {} cancelled", command.id);
+            command.finish(CommandState::Failed, CommandOutcome::Failed);
+            cancelled.push(command.id);
+        }
+        cancelled
+    }
+
+    /// Gives up on all the commands that are not finished, the bus went away.
+    pub fn fail_all(&mut self) {
+        for command in self.commands.iter_mut().filter(|c| !c.is_finished()) {
+            warn!("Command {} dropped, the connection was lost", command.id);
+            command.finish(CommandState::Failed, CommandOutcome::Failed);
+        }
+    }
+
+    /// True if the command is still waiting to be sent or acknowledged.
+    pub fn is_active(&self, id: CommandId) -> bool {
+        self.commands.iter().any(|c| c.id == id && !c.is_finished())
+    }
+
+    /// Handles commands that were not acknowledged in time. Returns the ones that have to
+    /// be sent again. A command that could not be finished within all of its attempts times
+    /// out whatever its state, so that nobody waits for it forever.
+    pub fn check_timeouts(&mut self, now: Instant) -> Vec<(CommandId, Vec<u8>)> {
+        let deadline = self.policy.timeout * (self.policy.retries + 1);
+        let mut resend = Vec::new();
+        for command in self.commands.iter_mut() {
+            if command.is_finished() {
+                continue;
+            }
+            if now.duration_since(command.queued_at) >= deadline {
+                warn!(
+                    "Command {} was not acknowledged in {:?}",
+                    command.id, deadline
+                );
+                command.finish(CommandState::Failed, CommandOutcome::TimedOut);
+                continue;
+            }
+            if command.state != CommandState::Sent {
+                continue;
+            }
+            let Some(sent_at) = command.sent_at else {
+                continue;
+            };
+            if now.duration_since(sent_at) < self.policy.timeout {
+                continue;
+            }
+            if command.attempts > self.policy.retries {
+                warn!("Command {} was not acknowledged", command.id);
+                command.finish(CommandState::Failed, CommandOutcome::TimedOut);
+            } else {
+                debug!("Command {} timed out, retrying", command.id);
+                command.state = CommandState::Retried;
+                resend.push((command.id, command.frame.clone()));
+            }
+        }
+        resend
+    }
+
+    fn find_active(&mut self, id: CommandId) -> Option<&mut Command> {
+        self.commands
+            .iter_mut()
+            .find(|c| c.id == id && !c.is_finished())
+    }
+
+    // Drops the oldest finished commands.
+    fn cleanup(&mut self) {
+        let finished = self.commands.iter().filter(|c| c.is_finished()).count();
+        let mut to_remove = finished.saturating_sub(FINISHED_COMMANDS_KEPT);
+        self.commands.retain(|c| {
+            if to_remove > 0 && c.is_finished() {
+                to_remove -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_acked() {
+        let mut tracker = CommandTracker::new(CommandPolicy::default());
+        let mut handle = tracker.add(vec![0x01], 0x10, 0x86, None);
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Queued));
+        assert!(tracker.is_pending(0x86));
+
+        // Nothing was sent yet, the ack is not ours.
+        assert_eq!(tracker.acknowledged(0x10, 0x86, None), None);
+
+        tracker.sent(handle.id, Instant::now());
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Sent));
+        // Some other device acknowledged the same action.
+        assert_eq!(tracker.acknowledged(0x60, 0x86, None), None);
+        assert_eq!(tracker.acknowledged(0x10, 0x86, None), Some(handle.id));
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Acked));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Acked);
+        assert!(!tracker.is_pending(0x86));
+    }
+
+    #[test]
+    fn test_command_retried_then_timed_out() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(100),
+        };
+        let mut tracker = CommandTracker::new(policy);
+        let mut handle = tracker.add(vec![0x01, 0x02], 0x10, 0x86, None);
+        let start = Instant::now();
+        tracker.sent(handle.id, start);
+
+        assert!(tracker
+            .check_timeouts(start + Duration::from_millis(50))
+            .is_empty());
+        assert_eq!(
+            tracker.check_timeouts(start + Duration::from_millis(100)),
+            vec![(handle.id, vec![0x01, 0x02])]
+        );
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Retried));
+
+        tracker.sent(handle.id, start + Duration::from_millis(200));
+        assert!(tracker
+            .check_timeouts(start + Duration::from_millis(300))
+            .is_empty());
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Failed));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::TimedOut);
+    }
+
+    #[test]
+    fn test_transmit_failed() {
+        let mut tracker = CommandTracker::new(CommandPolicy::default());
+        let mut handle = tracker.add(vec![0x01], 0x10, 0x86, None);
+        tracker.transmit_failed(handle.id);
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Failed));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Failed);
+    }
+
+    #[test]
+    fn test_never_sent_command_times_out() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(100),
+        };
+        let mut tracker = CommandTracker::new(policy);
+        let mut handle = tracker.add(vec![0x01], 0x10, 0x86, None);
+        let start = Instant::now();
+
+        assert!(tracker
+            .check_timeouts(start + Duration::from_millis(150))
+            .is_empty());
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Queued));
+        tracker.check_timeouts(start + Duration::from_millis(200));
+        assert_eq!(tracker.state(handle.id), Some(CommandState::Failed));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::TimedOut);
+        assert!(!tracker.is_active(handle.id));
+    }
+
+    #[test]
+    fn test_late_ack_of_retried_command() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(100),
+        };
+        let mut tracker = CommandTracker::new(policy);
+        let mut handle = tracker.add(vec![0x01], 0x10, 0x86, None);
+        let start = Instant::now();
+        tracker.sent(handle.id, start);
+        assert_eq!(
+            tracker
+                .check_timeouts(start + Duration::from_millis(100))
+                .len(),
+            1
+        );
+
+        // The answer to the first attempt shows up before the frame is sent again.
+        assert_eq!(tracker.acknowledged(0x10, 0x86, None), Some(handle.id));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Acked);
+    }
+}
@@ -0,0 +1,407 @@
+// Splits the bytes from the bus into frames. The decoder does no I/O: the reader pushes
+// whatever it got, from a blocking port or an async socket alike, and gets the complete frames
+// back. Garbage and broken frames are skipped and counted.
+
+use crate::pool::message::chlorinator;
+use std::collections::VecDeque;
+
+const HEADER: [u8; 4] = [0xFF, 0x00, 0xFF, 0xA5];
+/// The last byte of the header is in the checksum.
+const PACKET_START: u8 = 0xA5;
+/// Version, destination, source, action and the payload length.
+const PACKET_HEADER_LEN: usize = 5;
+const LENGTH_IDX: usize = 4;
+const CHECKSUM_LEN: usize = 2;
+const MAX_PACKET_LEN: usize = PACKET_HEADER_LEN + u8::MAX as usize + CHECKSUM_LEN;
+/// The longest chlorinator message is the name response: dest, action, model, 16 characters,
+/// the checksum and the frame end.
+const MAX_CHLORINATOR_LEN: usize = 24;
+/// The bus is pulled to 0xFF between the frames, such bytes are not garbage.
+const IDLE_BYTE: u8 = 0xFF;
+
+/// A complete frame with a valid checksum, without the framing bytes and the checksum.
+#[derive(Debug, PartialEq)]
+pub enum Frame<'a> {
+    Packet(&'a [u8]),
+    Chlorinator(&'a [u8]),
+}
+
+/// What the decoder threw away since the counters were taken last.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DecoderCounters {
+    /// Bytes outside of any frame.
+    pub unrecognized_bytes: u32,
+    /// Frames cut off by a pause on the bus.
+    pub short_packets: u32,
+    /// Frames with a wrong checksum or no end.
+    pub corrupted_packets: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Scanning,
+    Packet,
+    Chlorinator,
+}
+
+enum Step {
+    More,
+    Complete,
+    Invalid,
+}
+
+pub struct FrameDecoder {
+    state: State,
+    // While scanning the last bytes that may start a header, then the frame after it.
+    buffer: [u8; MAX_PACKET_LEN],
+    len: usize,
+    // Bytes of a broken frame that go through the decoder again, and how many of them are in
+    // the scan window: they were counted with the broken frame already.
+    replay: VecDeque<u8>,
+    replayed_in_window: usize,
+    counters: DecoderCounters,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder {
+            state: State::Scanning,
+            buffer: [0; MAX_PACKET_LEN],
+            len: 0,
+            // A replay is shorter than the broken frame it comes from.
+            replay: VecDeque::with_capacity(MAX_PACKET_LEN),
+            replayed_in_window: 0,
+            counters: DecoderCounters::default(),
+        }
+    }
+
+    /// Decodes a chunk of bytes, calls on_frame for every frame completed in it. A frame may
+    /// span any number of chunks.
+    pub fn push(&mut self, chunk: &[u8], on_frame: &mut impl FnMut(Frame<'_>)) {
+        for byte in chunk {
+            self.push_byte(*byte, false, on_frame);
+            while let Some(byte) = self.replay.pop_front() {
+                self.push_byte(byte, true, on_frame);
+            }
+        }
+    }
+
+    /// The bus went quiet: a frame that is not complete by now never will be.
+    pub fn idle(&mut self) {
+        if self.state != State::Scanning {
+            self.counters.short_packets += 1;
+        }
+        self.state = State::Scanning;
+        self.len = 0;
+        self.replayed_in_window = 0;
+    }
+
+    pub fn take_counters(&mut self) -> DecoderCounters {
+        std::mem::take(&mut self.counters)
+    }
+
+    fn push_byte(&mut self, byte: u8, replayed: bool, on_frame: &mut impl FnMut(Frame<'_>)) {
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        if replayed && self.state == State::Scanning {
+            self.replayed_in_window += 1;
+        }
+        match self.step() {
+            Step::More => {}
+            Step::Complete => {
+                let frame = match self.state {
+                    State::Packet => Frame::Packet(&self.buffer[..self.len - CHECKSUM_LEN]),
+                    _ => Frame::Chlorinator(
+                        &self.buffer[..self.len - 1 - chlorinator::FRAME_END.len()],
+                    ),
+                };
+                on_frame(frame);
+                self.state = State::Scanning;
+                self.len = 0;
+            }
+            Step::Invalid => {
+                // A real frame may start inside the broken one, look for it in the bytes after
+                // the broken frame start, before the bytes of an earlier replay.
+                self.counters.corrupted_packets += 1;
+                for byte in self.buffer[1..self.len].iter().rev() {
+                    self.replay.push_front(*byte);
+                }
+                self.state = State::Scanning;
+                self.len = 0;
+            }
+        }
+    }
+
+    fn step(&mut self) -> Step {
+        let frame = &self.buffer[..self.len];
+        match self.state {
+            State::Scanning => {
+                if frame.ends_with(&HEADER) {
+                    self.state = State::Packet;
+                    self.len = 0;
+                    self.replayed_in_window = 0;
+                } else if let Some(dest) = chlorinator_start(frame) {
+                    self.count_unrecognized(self.len - chlorinator::FRAME_START.len() - 1);
+                    self.state = State::Chlorinator;
+                    self.buffer[0] = dest;
+                    self.len = 1;
+                    self.replayed_in_window = 0;
+                } else if self.len == HEADER.len() {
+                    self.count_unrecognized(1);
+                    self.buffer.copy_within(1..HEADER.len(), 0);
+                    self.len -= 1;
+                }
+                Step::More
+            }
+            State::Packet => {
+                if self.len <= LENGTH_IDX {
+                    return Step::More;
+                }
+                let packet_len = PACKET_HEADER_LEN + frame[LENGTH_IDX] as usize;
+                if self.len < packet_len + CHECKSUM_LEN {
+                    return Step::More;
+                }
+                let checksum = frame[..packet_len]
+                    .iter()
+                    .fold(PACKET_START as u16, |sum, byte| {
+                        sum.wrapping_add(*byte as u16)
+                    });
+                if checksum.to_be_bytes() == frame[packet_len..] {
+                    Step::Complete
+                } else {
+                    Step::Invalid
+                }
+            }
+            State::Chlorinator => {
+                if !frame.ends_with(&chlorinator::FRAME_END) {
+                    return if self.len >= MAX_CHLORINATOR_LEN {
+                        Step::Invalid
+                    } else {
+                        Step::More
+                    };
+                }
+                // Destination, action and the checksum.
+                let message_len = self.len - chlorinator::FRAME_END.len();
+                match frame[..message_len].split_last() {
+                    Some((checksum, message))
+                        if message.len() >= 2 && chlorinator::checksum(message) == *checksum =>
+                    {
+                        Step::Complete
+                    }
+                    _ => Step::Invalid,
+                }
+            }
+        }
+    }
+
+    // The first bytes of the scan window are dropped, the replayed ones are not counted again.
+    fn count_unrecognized(&mut self, count: usize) {
+        let replayed = count.min(self.replayed_in_window);
+        self.replayed_in_window -= replayed;
+        let garbage = self.buffer[replayed..count]
+            .iter()
+            .filter(|byte| **byte != IDLE_BYTE)
+            .count();
+        self.counters.unrecognized_bytes += garbage as u32;
+    }
+}
+
+// `10 02` and the destination after it. Every status from the panel has `10 02` in its header
+// too, the destination tells a chlorinator frame from a packet read from its middle.
+fn chlorinator_start(window: &[u8]) -> Option<u8> {
+    match window {
+        [.., first, second, dest] if [*first, *second] == chlorinator::FRAME_START => {
+            chlorinator::is_frame_address(*dest).then_some(*dest)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const PACKET: [u8; 12] = [
+        0xFF, 0x00, 0xFF, 0xA5, 0x01, 0x24, 0x10, 0x01, 0x01, 0x86, 0x01, 0x62,
+    ];
+    const CHLORINATOR: [u8; 8] = [0x10, 0x02, 0x00, 0x12, 0x4F, 0x81, 0xF4, 0x10];
+
+    fn decode(decoder: &mut FrameDecoder, chunks: &[&[u8]]) -> Vec<(bool, Vec<u8>)> {
+        let mut frames = Vec::new();
+        for chunk in chunks {
+            decoder.push(chunk, &mut |frame| {
+                frames.push(match frame {
+                    Frame::Packet(packet) => (true, packet.to_vec()),
+                    Frame::Chlorinator(packet) => (false, packet.to_vec()),
+                })
+            });
+        }
+        frames
+    }
+
+    #[test]
+    fn test_frames_across_chunks() {
+        let mut decoder = FrameDecoder::new();
+        // One byte of garbage and the idle bus before the frames.
+        let mut stream = vec![0x12, 0xFF];
+        stream.extend(PACKET);
+        stream.extend(CHLORINATOR);
+        stream.push(0x03);
+        // Every split of the stream into two chunks gives the same frames.
+        for split in 0..stream.len() {
+            let frames = decode(&mut decoder, &[&stream[..split], &stream[split..]]);
+            assert_eq!(
+                frames,
+                vec![
+                    (true, PACKET[4..10].to_vec()),
+                    (false, vec![0x00, 0x12, 0x4F, 0x81]),
+                ]
+            );
+        }
+        let passes = stream.len() as u32;
+        assert_eq!(
+            decoder.take_counters(),
+            DecoderCounters {
+                unrecognized_bytes: passes,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_resync_after_bad_frames() {
+        let mut decoder = FrameDecoder::new();
+        // The length of the broken packet swallows the next one, it is found again.
+        let mut broken = PACKET;
+        broken[8] = 0x0A;
+        let frames = decode(&mut decoder, &[&broken, &PACKET, &[0x00]]);
+        assert_eq!(frames, vec![(true, PACKET[4..10].to_vec())]);
+        // The bytes skipped in the broken packet are not garbage as well.
+        assert_eq!(
+            decoder.take_counters(),
+            DecoderCounters {
+                corrupted_packets: 1,
+                ..Default::default()
+            }
+        );
+
+        // Wrong chlorinator checksum.
+        let mut chlorinator = CHLORINATOR;
+        chlorinator[6] = 0xF5;
+        let frames = decode(&mut decoder, &[&chlorinator, &[0x03], &PACKET]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(decoder.take_counters().corrupted_packets, 1);
+
+        // Read from the middle of a status, its 10 02 does not start a chlorinator frame.
+        let status_tail = [0x01, 0x0F, 0x10, 0x02, 0x1D, 0x0A, 0x1A, 0x20];
+        let frames = decode(&mut decoder, &[&status_tail, &PACKET]);
+        assert_eq!(frames, vec![(true, PACKET[4..10].to_vec())]);
+        assert_eq!(
+            decoder.take_counters(),
+            DecoderCounters {
+                unrecognized_bytes: status_tail.len() as u32,
+                ..Default::default()
+            }
+        );
+
+        // The bus goes quiet in the middle of a frame.
+        assert!(decode(&mut decoder, &[&PACKET[..8]]).is_empty());
+        decoder.idle();
+        assert_eq!(decode(&mut decoder, &[&PACKET]).len(), 1);
+        assert_eq!(decoder.take_counters().short_packets, 1);
+    }
+
+    // A frame on the bus and what the decoder should give back for it.
+    fn valid_frame() -> impl Strategy<Value = (Vec<u8>, (bool, Vec<u8>))> {
+        let packet = (
+            proptest::collection::vec(any::<u8>(), 4),
+            proptest::collection::vec(any::<u8>(), 0..=255),
+        )
+            .prop_map(|(header, payload)| {
+                let mut packet = header;
+                packet.push(payload.len() as u8);
+                packet.extend(payload);
+                let sum = packet
+                    .iter()
+                    .fold(PACKET_START as u16, |sum, b| sum.wrapping_add(*b as u16));
+                let mut frame = HEADER.to_vec();
+                frame.extend(&packet);
+                frame.extend(sum.to_be_bytes());
+                (frame, (true, packet))
+            });
+        // The chlorinator framing has no escaping, a 10 in the message would end it early.
+        let chlorinator = (
+            prop::sample::select(vec![0x00, 0x50, 0x51, 0x52, 0x53]),
+            proptest::collection::vec(any::<u8>().prop_filter("frame end", |b| *b != 0x10), 1..=18),
+        )
+            .prop_map(|(dest, message)| {
+                let frame = chlorinator::encode_packet(dest, message[0], &message[1..]);
+                let mut packet = vec![dest];
+                packet.extend(message);
+                (frame, (false, packet))
+            })
+            .prop_filter("frame end in the checksum", |(frame, _)| {
+                frame[frame.len() - 3] != 0x10
+            });
+        prop_oneof![packet, chlorinator]
+    }
+
+    proptest! {
+        // Frames with noise between them that cannot start a frame, cut into chunks anywhere.
+        #[test]
+        fn test_any_chunks_give_the_frames(
+            frames in proptest::collection::vec(
+                (
+                    proptest::collection::vec(
+                        any::<u8>().prop_filter("frame start", |b| {
+                            *b != PACKET_START && *b != chlorinator::FRAME_START[1]
+                        }),
+                        0..8,
+                    ),
+                    valid_frame(),
+                ),
+                0..6,
+            ),
+            cuts in proptest::collection::vec(any::<prop::sample::Index>(), 0..8),
+        ) {
+            let mut stream = Vec::new();
+            let mut expected = Vec::new();
+            for (noise, (frame, decoded)) in frames {
+                stream.extend(noise);
+                stream.extend(frame);
+                expected.push(decoded);
+            }
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(stream.len() + 1)).collect();
+            cuts.push(0);
+            cuts.push(stream.len());
+            cuts.sort();
+            let chunks: Vec<&[u8]> = cuts.windows(2).map(|cut| &stream[cut[0]..cut[1]]).collect();
+
+            let mut decoder = FrameDecoder::new();
+            prop_assert_eq!(decode(&mut decoder, &chunks), expected);
+            prop_assert_eq!(decoder.take_counters().corrupted_packets, 0);
+        }
+
+        #[test]
+        fn test_any_bytes_do_not_panic(
+            chunks in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 0..300), 0..8),
+            idle: Option<prop::sample::Index>,
+        ) {
+            let mut decoder = FrameDecoder::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                decoder.push(chunk, &mut |_| {});
+                if idle.is_some_and(|idle| idle.index(chunks.len()) == i) {
+                    decoder.idle();
+                }
+            }
+            decoder.take_counters();
+        }
+    }
+}
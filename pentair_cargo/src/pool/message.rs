@@ -1,32 +1,98 @@
-
-use serial::Error;
+use crate::error::PoolError;
+pub mod chlorinator;
+pub mod circuit;
+pub mod clock;
+pub mod dissector;
+pub mod equipment;
+pub mod heat;
+pub mod intellichem;
+pub mod light;
+pub mod panel_config;
+pub mod pump_state;
+pub mod schedule;
 pub mod system_state;
 
-
 #[derive(Clone, Debug)]
 pub enum PacketType {
     Status(system_state::SystemState),
-    CircuitStatusChange, // We'd ignore it.
-    CircuitStatusResponse,
+    CircuitStatusChange {
+        circuit: u8,
+        state: bool,
+    }, // Request to the panel, we'd ignore it.
+    CircuitStatusResponse(u8), // Acknowledgement, carries the acknowledged action.
     RemoteLayoutRequest,
-    RemoteLayoutResponse,
-    ClockBroadcast,
-    PumpStatus,
+    RemoteLayoutResponse(Vec<u8>), // Remote id and the circuits on its buttons.
+    ConfigRequest(u8), // Somebody asks the panel for its configuration, carries the action.
+    CircuitDefinition(panel_config::CircuitDefinition),
+    CustomName {
+        index: u8,
+        name: String,
+    },
+    Schedule {
+        id: u8,
+        schedule: Option<schedule::Schedule>,
+    }, // None for an empty slot.
+    SetSchedule {
+        id: u8,
+        schedule: Option<schedule::Schedule>,
+    }, // Request to the panel.
+    ValveConfig(equipment::ValveConfig),
+    SetValveConfig(equipment::ValveConfig), // Request to the panel.
+    SolarConfig(equipment::SolarConfig),
+    SetSolarConfig(equipment::SolarConfig), // Request to the panel.
+    LightCommand(light::LightTheme),        // Request to the panel.
+    LightGroup(light::LightGroup),
+    SetLightGroup(light::LightGroup), // Request to the panel.
+    ClockBroadcast(clock::PanelClock),
+    SetClock(clock::PanelClock), // Request to the panel.
+    HeatStatus(heat::HeatSettings),
+    SetHeat(heat::HeatSettings), // Request to the panel.
+    PumpStatus(pump_state::PumpState),
+    PumpStatusRequest, // The panel asks a pump for its status.
+    PumpReply(u8),     // A pump confirms a command, carries the action.
+    ChemStatus(intellichem::ChemistryState),
+    ChemStatusRequest, // The panel asks IntelliChem for its status.
     Unknown,
 }
 
 #[derive(Clone, Debug)]
-pub struct ProtocolPacket{
-    packet_content: Vec<u8>,  
-    pub decoded: PacketType
+pub struct ProtocolPacket {
+    packet_content: Vec<u8>,
+    pub decoded: PacketType,
 }
 
+/// Address of the main panel (EasyTouch/IntelliTouch) on the bus.
+pub const PANEL_ADDRESS: u8 = 0x10;
+/// Destination of the status broadcasts.
+pub const BROADCAST_ADDRESS: u8 = 0x0F;
+
+/// The panel acknowledges a command with the action of it in the payload.
+pub const ACK_ACTION: u8 = 0x01;
+/// The status broadcast of the panel.
+pub const STATUS_ACTION: u8 = 0x02;
+/// Action codes we send to the panel.
+pub const SET_CIRCUIT_ACTION: u8 = 0x86;
+/// The panel firmware version and the request for it.
+pub const VERSION_ACTION: u8 = 0xFC;
+pub const VERSION_REQUEST: u8 = 0xFD;
+
+/// Bytes in front of every packet on the bus.
+const PREAMBLE: [u8; 3] = [0xFF, 0x00, 0xFF];
+/// The byte that starts a packet, it is included in the checksum.
+const PACKET_START: u8 = 0xA5;
+
+/// The length of the payload is sent in a byte.
+pub const MAX_PAYLOAD_LEN: usize = u8::MAX as usize;
+
 // Block protocol offsets in the packet without any header.
 
 const PROTOCOL_OFFSET: usize = 0;
 const DEST_OFFSET: usize = 1;
 const SRC_OFFSET: usize = 2;
 const CMD_OFFSET: usize = 3;
+/// Offsets are in the packet without the header, the payload starts at 5.
+pub const PAYLOAD_IDX: usize = 5;
+
 impl ProtocolPacket {
     pub fn new(packet: &[u8]) -> ProtocolPacket {
         ProtocolPacket {
@@ -34,52 +100,260 @@ impl ProtocolPacket {
             decoded: PacketType::Unknown,
         }
     }
+    // The getters return 0 for the fields missing in a short packet.
     pub fn get_source(&self) -> u8 {
-        if self.packet_content.len() < SRC_OFFSET {
-            return 0;
-        }
-        self.packet_content[SRC_OFFSET]
+        self.packet_content
+            .get(SRC_OFFSET)
+            .copied()
+            .unwrap_or_default()
     }
     pub fn get_destination(&self) -> u8 {
-        if self.packet_content.len() < DEST_OFFSET {
-            return 0;
-        }
-        self.packet_content[DEST_OFFSET]
+        self.packet_content
+            .get(DEST_OFFSET)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn get_action(&self) -> u8 {
+        self.packet_content
+            .get(CMD_OFFSET)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Data after the header, empty if the packet is too short.
+    pub fn get_payload(&self) -> &[u8] {
+        self.packet_content.get(PAYLOAD_IDX..).unwrap_or_default()
+    }
+
+    /// The whole packet without the preamble and the checksum.
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.packet_content
     }
 
     pub fn get_protocol_version(&self) -> u8 {
-        if self.packet_content.len() < 1 {
-            return 0;
-        }
-        self.packet_content[PROTOCOL_OFFSET]
+        self.packet_content
+            .get(PROTOCOL_OFFSET)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Builds a complete frame ready to be written to the bus: preamble, header,
+    /// payload and the two byte checksum. Fails when the payload does not fit.
+    pub fn encode_packet(
+        protocol_version: u8,
+        dest: u8,
+        source: u8,
+        action: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, PoolError> {
+        Ok(PacketBuilder::new(dest, source, action)
+            .protocol_version(protocol_version)
+            .payload(payload)?
+            .build())
+    }
+
+    /// The frame of this packet as it was on the bus.
+    pub fn encode(&self) -> Result<Vec<u8>, PoolError> {
+        Ok(
+            PacketBuilder::new(self.get_destination(), self.get_source(), self.get_action())
+                .protocol_version(self.get_protocol_version())
+                .payload(self.get_payload())?
+                .build(),
+        )
     }
 
-    pub fn decode_packet(packet: &[u8] ) -> Result<ProtocolPacket, serial::Error> {
+    /// Checks the header of a packet and keeps its bytes, the payload is left undecoded.
+    pub fn decode_header(packet: &[u8]) -> Result<ProtocolPacket, PoolError> {
         if packet.len() < 4 {
-            return Err(Error::new(
-                serial::ErrorKind::InvalidInput,
-                "Packet is too short (decode_packet)",
-            ));
+            return Err(PoolError::Framing("Packet is too short for the header"));
         }
         if packet[PROTOCOL_OFFSET] != 0x00 && packet[PROTOCOL_OFFSET] != 0x01 {
-            return Err(Error::new(
-                serial::ErrorKind::InvalidInput,
-                "Invalid protocol version",
-            ));
+            return Err(PoolError::UnsupportedProtocol(packet[PROTOCOL_OFFSET]));
         }
-        Ok(ProtocolPacket{
-            packet_content: packet.to_vec(),
-            decoded: match packet[CMD_OFFSET] {
-                0x02 if packet[SRC_OFFSET] == 0x10 && packet[DEST_OFFSET] == 0x0f => PacketType::Status(system_state::SystemState::from_packet(packet)?),
-                0x86 => PacketType::CircuitStatusChange,
-                0x01 => PacketType::CircuitStatusResponse,
-                0xE1 => PacketType::RemoteLayoutRequest,
-                0x21 => PacketType::RemoteLayoutResponse,
-                0x05 => PacketType::ClockBroadcast,
-                0x07 => PacketType::PumpStatus,
-                _ => PacketType::Unknown,
+        Ok(ProtocolPacket::new(packet))
+    }
+
+    /// Decodes the packets from and to the pumps and IntelliChem, they are the same with every
+    /// panel. None for the other packets, their actions depend on the board.
+    pub fn decode_device_payload(&self) -> Result<Option<PacketType>, PoolError> {
+        let packet = &self.packet_content[..];
+        let (source, dest) = (self.get_source(), self.get_destination());
+        let is_pump = pump_state::PumpState::is_pump_address;
+        let is_chem = intellichem::ChemistryState::is_chem_address;
+        if !is_pump(source) && !is_pump(dest) && !is_chem(source) && !is_chem(dest) {
+            return Ok(None);
+        }
+        let action = self.get_action();
+        Ok(Some(match action {
+            pump_state::PUMP_STATUS_ACTION if is_pump(source) => {
+                PacketType::PumpStatus(pump_state::PumpState::from_packet(packet)?)
+            }
+            pump_state::SET_SPEED_ACTION
+            | pump_state::REMOTE_CONTROL_ACTION
+            | pump_state::SET_MODE_ACTION
+            | pump_state::RUN_ACTION
+                if is_pump(source) =>
+            {
+                PacketType::PumpReply(action)
+            }
+            pump_state::PUMP_STATUS_ACTION if is_pump(dest) => PacketType::PumpStatusRequest,
+            intellichem::CHEM_STATUS_ACTION if is_chem(source) => {
+                PacketType::ChemStatus(intellichem::ChemistryState::from_packet(packet)?)
             }
-        })
+            intellichem::CHEM_STATUS_REQUEST if is_chem(dest) => PacketType::ChemStatusRequest,
+            _ => PacketType::Unknown,
+        }))
     }
+}
+
+/// Builds a frame for the bus from its fields, the preamble, the length and the checksum are
+/// added. The protocol version is 0x01 unless set, pumps use 0x00.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PacketBuilder {
+    protocol_version: u8,
+    dest: u8,
+    source: u8,
+    action: u8,
+    payload: Vec<u8>,
+}
 
+impl PacketBuilder {
+    pub fn new(dest: u8, source: u8, action: u8) -> PacketBuilder {
+        PacketBuilder {
+            protocol_version: 0x01,
+            dest,
+            source,
+            action,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn protocol_version(mut self, protocol_version: u8) -> PacketBuilder {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Fails when the payload is longer than MAX_PAYLOAD_LEN, its length would not fit.
+    pub fn payload(mut self, payload: &[u8]) -> Result<PacketBuilder, PoolError> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(PoolError::PayloadTooLong(payload.len()));
+        }
+        self.payload = payload.to_vec();
+        Ok(self)
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut frame =
+            Vec::with_capacity(PREAMBLE.len() + 1 + PAYLOAD_IDX + self.payload.len() + 2);
+        frame.extend_from_slice(&PREAMBLE);
+        frame.push(PACKET_START);
+        frame.extend_from_slice(&[
+            self.protocol_version,
+            self.dest,
+            self.source,
+            self.action,
+            self.payload.len() as u8,
+        ]);
+        frame.extend_from_slice(&self.payload);
+        // At most 261 bytes of 255, the sum fits.
+        let checksum: u16 = frame[PREAMBLE.len()..].iter().map(|b| *b as u16).sum();
+        frame.extend_from_slice(&checksum.to_be_bytes());
+        frame
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_encode_packet() {
+    // Turn circuit 1 (spa) on, sent from 0x48.
+    let frame =
+        ProtocolPacket::encode_packet(0x01, PANEL_ADDRESS, 0x48, SET_CIRCUIT_ACTION, &[0x01, 0x01])
+            .unwrap();
+    assert_eq!(
+        frame,
+        vec![0xFF, 0x00, 0xFF, 0xA5, 0x01, 0x10, 0x48, 0x86, 0x02, 0x01, 0x01, 0x01, 0x88]
+    );
+    assert!(matches!(
+        PacketBuilder::new(PANEL_ADDRESS, 0x48, 0x11).payload(&[0; MAX_PAYLOAD_LEN + 1]),
+        Err(PoolError::PayloadTooLong(256))
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::board::BoardKind;
+    use crate::pool::frame::{Frame, FrameDecoder};
+    use proptest::prelude::*;
+
+    proptest! {
+        // What we build is read back as the same packet and encodes to the same bytes.
+        #[test]
+        fn test_encode_round_trip(
+            protocol_version in 0u8..=1,
+            dest: u8,
+            source: u8,
+            action: u8,
+            payload in proptest::collection::vec(any::<u8>(), 0..=MAX_PAYLOAD_LEN),
+        ) {
+            let frame = PacketBuilder::new(dest, source, action)
+                .protocol_version(protocol_version)
+                .payload(&payload)
+                .unwrap()
+                .build();
+            let mut packets = Vec::new();
+            FrameDecoder::new().push(&frame, &mut |decoded| {
+                if let Frame::Packet(packet) = decoded {
+                    packets.push(packet.to_vec());
+                }
+            });
+            prop_assert_eq!(packets.len(), 1);
+            let packet = &packets[0];
+            prop_assert_eq!(&packet[..], &frame[PREAMBLE.len() + 1..frame.len() - 2]);
+
+            // The header of every frame decodes, only the typed payload decoders may refuse.
+            let decoded = ProtocolPacket::decode_header(packet);
+            prop_assert!(decoded.is_ok(), "{:?}", decoded);
+            let decoded = decoded.unwrap();
+            prop_assert_eq!(
+                (decoded.get_protocol_version(), decoded.get_destination(), decoded.get_source(), decoded.get_action()),
+                (protocol_version, dest, source, action)
+            );
+            prop_assert_eq!(decoded.get_payload(), &payload[..]);
+            prop_assert_eq!(decoded.encode().unwrap(), frame.clone());
+            for kind in [BoardKind::EasyTouch, BoardKind::IntelliCenter] {
+                match kind.board().decode_packet(packet) {
+                    Ok(typed) => prop_assert_eq!(typed.encode().unwrap(), frame.clone()),
+                    Err(e) => prop_assert!(
+                        matches!(e, PoolError::PayloadTooShort(_) | PoolError::InvalidPayload(_)),
+                        "{}", e
+                    ),
+                }
+            }
+        }
+    }
+
+    proptest! {
+        // Payload and packet decoders take any bytes, the broken ones are rejected.
+        #[test]
+        fn test_decoders_do_not_panic(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = panel_config::CircuitDefinition::from_payload(&bytes);
+            let _ = panel_config::decode_custom_name(&bytes);
+            let _ = schedule::Schedule::from_payload(&bytes);
+            let _ = equipment::ValveConfig::from_payload(&bytes);
+            let _ = equipment::SolarConfig::from_payload(&bytes);
+            let _ = light::LightGroup::from_payload(&bytes);
+            let _ = heat::HeatSettings::from_set_payload(&bytes);
+            let _ = chlorinator::ChlorinatorMessage::decode(&bytes);
+            let _ = system_state::SystemState::from_packet(&bytes);
+            let _ = clock::PanelClock::from_packet(&bytes);
+            let _ = heat::HeatSettings::from_packet(&bytes);
+            let _ = pump_state::PumpState::from_packet(&bytes);
+            let _ = intellichem::ChemistryState::from_packet(&bytes);
+            for kind in [BoardKind::EasyTouch, BoardKind::IntelliCenter] {
+                let _ = kind.board().decode_packet(&bytes);
+            }
+        }
+    }
 }
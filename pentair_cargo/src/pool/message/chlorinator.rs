@@ -0,0 +1,187 @@
+// IntelliChlor salt chlorinator messages. They don't use the A5 framing:
+// 10 02 <dest> <action> <payload> <checksum> 10 03, the checksum is the low byte of the
+// sum of everything before it, 10 02 included.
+
+use crate::error::PoolError;
+use serde::Serialize;
+
+/// Bytes around every chlorinator frame.
+pub const FRAME_START: [u8; 2] = [0x10, 0x02];
+pub const FRAME_END: [u8; 2] = [0x10, 0x03];
+
+/// Address of the first chlorinator, the master (panel or us) is 0x00.
+pub const CHLORINATOR_ADDRESS: u8 = 0x50;
+pub const MASTER_ADDRESS: u8 = 0x00;
+/// Up to four chlorinators, from CHLORINATOR_ADDRESS on.
+const LAST_CHLORINATOR_ADDRESS: u8 = 0x53;
+
+/// The destination of every chlorinator frame, the master or a chlorinator.
+pub fn is_frame_address(address: u8) -> bool {
+    address == MASTER_ADDRESS || (CHLORINATOR_ADDRESS..=LAST_CHLORINATOR_ADDRESS).contains(&address)
+}
+
+/// The master checks that the chlorinator is there.
+pub const PROBE_ACTION: u8 = 0x00;
+pub const PROBE_RESPONSE_ACTION: u8 = 0x01;
+pub const NAME_RESPONSE_ACTION: u8 = 0x03;
+/// The master sets the output, the chlorinator answers with its status.
+pub const SET_OUTPUT_ACTION: u8 = 0x11;
+pub const STATUS_ACTION: u8 = 0x12;
+pub const NAME_REQUEST_ACTION: u8 = 0x14;
+
+/// Outputs above 100% ask for super chlorination.
+pub const SUPER_CHLORINATE_OUTPUT: u8 = 101;
+/// The salt level is sent in units of 50 ppm.
+const SALT_PPM_UNIT: u16 = 50;
+
+// Offsets in the packet without the frame start and the checksum.
+const DEST_IDX: usize = 0;
+const ACTION_IDX: usize = 1;
+const PAYLOAD_IDX: usize = 2;
+const NAME_LEN: usize = 16;
+
+/// Status bits of the set output response.
+const FAULTS: [(u8, &str); 8] = [
+    (0x01, "low flow"),
+    (0x02, "low salt"),
+    (0x04, "very low salt"),
+    (0x08, "high current"),
+    (0x10, "clean cell"),
+    (0x20, "low voltage"),
+    (0x40, "low water temperature"),
+    (0x80, "check PCB"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChlorinatorMessage {
+    Probe,
+    ProbeResponse,
+    Name(String),
+    NameRequest,
+    /// Output in percent, see SUPER_CHLORINATE_OUTPUT.
+    SetOutput(u8),
+    Status {
+        salt_ppm: u16,
+        status: u8,
+    },
+    Unknown,
+}
+
+impl ChlorinatorMessage {
+    /// Decodes a packet without the frame start, the checksum and the frame end.
+    pub fn decode(packet: &[u8]) -> Result<ChlorinatorMessage, PoolError> {
+        if packet.len() < PAYLOAD_IDX {
+            return Err(PoolError::PayloadTooShort("Chlorinator packet"));
+        }
+        let payload = &packet[PAYLOAD_IDX..];
+        Ok(match (packet[ACTION_IDX], payload) {
+            (PROBE_ACTION, _) => ChlorinatorMessage::Probe,
+            (PROBE_RESPONSE_ACTION, _) => ChlorinatorMessage::ProbeResponse,
+            // The first byte is the model.
+            (NAME_RESPONSE_ACTION, [_, name @ ..]) => ChlorinatorMessage::Name(
+                String::from_utf8_lossy(&name[..name.len().min(NAME_LEN)])
+                    .trim_end_matches(['\0', ' '])
+                    .to_string(),
+            ),
+            (NAME_REQUEST_ACTION, _) => ChlorinatorMessage::NameRequest,
+            (SET_OUTPUT_ACTION, [output, ..]) => ChlorinatorMessage::SetOutput(*output),
+            (STATUS_ACTION, [salt, status, ..]) => ChlorinatorMessage::Status {
+                salt_ppm: *salt as u16 * SALT_PPM_UNIT,
+                status: *status,
+            },
+            _ => ChlorinatorMessage::Unknown,
+        })
+    }
+
+    pub fn get_destination(packet: &[u8]) -> u8 {
+        packet.get(DEST_IDX).copied().unwrap_or_default()
+    }
+}
+
+/// Checksum of a frame: the low byte of the sum of the frame start, header and payload.
+pub fn checksum(packet: &[u8]) -> u8 {
+    FRAME_START
+        .iter()
+        .chain(packet)
+        .fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Builds a complete frame ready to be written to the bus.
+pub fn encode_packet(dest: u8, action: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![dest, action];
+    packet.extend_from_slice(payload);
+    let mut frame = FRAME_START.to_vec();
+    frame.extend_from_slice(&packet);
+    frame.push(checksum(&packet));
+    frame.extend_from_slice(&FRAME_END);
+    frame
+}
+
+/// What we know about the chlorinator from the traffic on the bus.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ChlorinatorState {
+    pub name: String,
+    pub salt_ppm: u16,
+    /// The output the master asked for, in percent.
+    pub output_percent: u8,
+    pub super_chlorinate: bool,
+    /// Raw status bits of the last response.
+    pub status: u8,
+    pub faults: Vec<String>,
+}
+
+impl ChlorinatorState {
+    /// Updates the state from a message, returns false if it carried nothing for us.
+    pub fn update(&mut self, message: &ChlorinatorMessage) -> bool {
+        match message {
+            ChlorinatorMessage::Name(name) => self.name = name.clone(),
+            ChlorinatorMessage::SetOutput(output) => {
+                self.super_chlorinate = *output >= SUPER_CHLORINATE_OUTPUT;
+                self.output_percent = (*output).min(100);
+            }
+            ChlorinatorMessage::Status { salt_ppm, status } => {
+                self.salt_ppm = *salt_ppm;
+                self.status = *status;
+                self.faults = FAULTS
+                    .iter()
+                    .filter(|(mask, _)| status & mask != 0)
+                    .map(|(_, name)| name.to_string())
+                    .collect();
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_chlorinator_messages() {
+    // Set output to 40%, as sent by the panel.
+    let frame = encode_packet(CHLORINATOR_ADDRESS, SET_OUTPUT_ACTION, &[40]);
+    assert_eq!(frame, vec![0x10, 0x02, 0x50, 0x11, 0x28, 0x9B, 0x10, 0x03]);
+
+    let mut state = ChlorinatorState::default();
+    let message = ChlorinatorMessage::decode(&frame[2..5]).unwrap();
+    assert_eq!(message, ChlorinatorMessage::SetOutput(40));
+    assert!(state.update(&message));
+    let message = ChlorinatorMessage::decode(&[0x00, 0x12, 0x4F, 0x81]).unwrap();
+    assert!(state.update(&message));
+    let message = ChlorinatorMessage::decode(b"\x00\x03\x00Intellichlor--40").unwrap();
+    assert!(state.update(&message));
+    assert_eq!(
+        state,
+        ChlorinatorState {
+            name: "Intellichlor--40".to_string(),
+            salt_ppm: 3950,
+            output_percent: 40,
+            super_chlorinate: false,
+            status: 0x81,
+            faults: vec!["low flow".to_string(), "check PCB".to_string()],
+        }
+    );
+
+    state.update(&ChlorinatorMessage::SetOutput(SUPER_CHLORINATE_OUTPUT));
+    assert!(state.super_chlorinate);
+    assert!(ChlorinatorMessage::decode(&[0x00]).is_err());
+}
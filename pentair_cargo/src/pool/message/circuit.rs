@@ -0,0 +1,97 @@
+// Circuits of the panel: what they are and whether they are on.
+
+use serde::Serialize;
+
+/// What a circuit drives, as configured on the panel.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitFunction {
+    Generic,
+    Spa,
+    Pool,
+    MasterCleaner,
+    Light,
+    SamLight,
+    SalLight,
+    PhotonGen,
+    ColorWheel,
+    Valve,
+    Spillway,
+    FloorCleaner,
+    IntelliBrite,
+    MagicStream,
+    NotUsed,
+    Other(u8),
+}
+
+impl CircuitFunction {
+    /// Decodes the function code of the circuit definition.
+    pub fn from_code(code: u8) -> CircuitFunction {
+        match code {
+            0 => CircuitFunction::Generic,
+            1 => CircuitFunction::Spa,
+            2 => CircuitFunction::Pool,
+            5 => CircuitFunction::MasterCleaner,
+            7 => CircuitFunction::Light,
+            9 => CircuitFunction::SamLight,
+            10 => CircuitFunction::SalLight,
+            11 => CircuitFunction::PhotonGen,
+            12 => CircuitFunction::ColorWheel,
+            13 => CircuitFunction::Valve,
+            14 => CircuitFunction::Spillway,
+            15 => CircuitFunction::FloorCleaner,
+            16 => CircuitFunction::IntelliBrite,
+            17 => CircuitFunction::MagicStream,
+            19 => CircuitFunction::NotUsed,
+            code => CircuitFunction::Other(code),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Circuit {
+    pub number: u8,
+    /// Name of the control, "pool" or "aux1", it stays when the panel names the circuit.
+    pub id: String,
+    /// Name to show, from the panel configuration or the device names.
+    pub name: String,
+    pub function: CircuitFunction,
+    /// The panel turns the circuit on when the air is close to freezing.
+    pub freeze_protect: bool,
+    pub on: bool,
+}
+
+impl Circuit {
+    /// A circuit we know nothing about but its number.
+    pub fn new(number: u8) -> Circuit {
+        let (name, function) = match number {
+            1 => ("spa".to_string(), CircuitFunction::Spa),
+            2..=4 => (format!("aux{}", number - 1), CircuitFunction::Generic),
+            5 => ("feature1".to_string(), CircuitFunction::Generic),
+            6 => ("pool".to_string(), CircuitFunction::Pool),
+            7 | 8 => (format!("feature{}", number - 5), CircuitFunction::Generic),
+            _ => (format!("circuit{}", number), CircuitFunction::Generic),
+        };
+        Circuit {
+            number,
+            id: name.clone(),
+            name,
+            function,
+            freeze_protect: false,
+            on: false,
+        }
+    }
+
+    /// The circuits of a panel with the default configuration.
+    pub fn defaults() -> Vec<Circuit> {
+        (1..=8).map(Circuit::new).collect()
+    }
+}
+
+/// Circuit names as they are compared: case and spaces are ignored, "Aux 1" is "AUX1".
+pub fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_uppercase)
+        .collect()
+}
@@ -0,0 +1,101 @@
+// The panel clock, broadcast with action 0x05 and set with 0x85. The payload is
+// hour, minute, day of week (bit 0 is Sunday), day, month, year - 2000, adjustment, auto DST.
+
+use crate::error::PoolError;
+use crate::pool::message::PAYLOAD_IDX;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::Serialize;
+
+pub const CLOCK_ACTION: u8 = 0x05;
+pub const SET_CLOCK_ACTION: u8 = 0x85;
+pub const CLOCK_REQUEST: u8 = 0xC5;
+
+const HOUR_IDX: usize = PAYLOAD_IDX;
+const MINUTE_IDX: usize = 6;
+const DAY_OF_WEEK_IDX: usize = 7;
+const DAY_IDX: usize = 8;
+const MONTH_IDX: usize = 9;
+const YEAR_IDX: usize = 10;
+const ADJUSTMENT_IDX: usize = 11;
+const DST_IDX: usize = 12;
+const CLOCK_PAYLOAD_LEN: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PanelClock {
+    /// Local time of the panel, it has only minutes.
+    pub time: NaiveDateTime,
+    /// The panel switches to and from daylight saving time by itself.
+    pub auto_dst: bool,
+    /// Clock speed correction as set on the panel.
+    pub adjustment: u8,
+}
+
+impl PanelClock {
+    pub fn from_packet(packet: &[u8]) -> Result<PanelClock, PoolError> {
+        if packet.len() < HOUR_IDX + CLOCK_PAYLOAD_LEN {
+            return Err(PoolError::PayloadTooShort("Clock packet"));
+        }
+        let time = NaiveDate::from_ymd_opt(
+            2000 + packet[YEAR_IDX] as i32,
+            packet[MONTH_IDX] as u32,
+            packet[DAY_IDX] as u32,
+        )
+        .and_then(|date| date.and_hms_opt(packet[HOUR_IDX] as u32, packet[MINUTE_IDX] as u32, 0))
+        .ok_or(PoolError::InvalidPayload("Invalid panel time"))?;
+        Ok(PanelClock {
+            time,
+            auto_dst: packet[DST_IDX] != 0,
+            adjustment: packet[ADJUSTMENT_IDX],
+        })
+    }
+
+    /// The clock set to the given time, e.g. the host time.
+    pub fn from_time<Tz: TimeZone>(time: &DateTime<Tz>, auto_dst: bool) -> PanelClock {
+        PanelClock {
+            time: time.naive_local(),
+            auto_dst,
+            adjustment: 0,
+        }
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut payload = vec![0; CLOCK_PAYLOAD_LEN];
+        let mut set = |idx: usize, value: u8| payload[idx - HOUR_IDX] = value;
+        set(HOUR_IDX, self.time.hour() as u8);
+        set(MINUTE_IDX, self.time.minute() as u8);
+        set(
+            DAY_OF_WEEK_IDX,
+            1 << self.time.weekday().num_days_from_sunday(),
+        );
+        set(DAY_IDX, self.time.day() as u8);
+        set(MONTH_IDX, self.time.month() as u8);
+        set(YEAR_IDX, (self.time.year() % 100) as u8);
+        set(ADJUSTMENT_IDX, self.adjustment);
+        set(DST_IDX, self.auto_dst as u8);
+        payload
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_panel_clock() {
+    // Sunday 2026-10-18 09:41, auto DST.
+    let packet = vec![
+        0x01, 0x0F, 0x10, 0x05, 0x08, 0x09, 0x29, 0x01, 0x12, 0x0A, 0x1A, 0x00, 0x01,
+    ];
+    let clock = PanelClock::from_packet(&packet).unwrap();
+    assert_eq!(
+        clock.time,
+        NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(9, 41, 0)
+            .unwrap()
+    );
+    assert!(clock.auto_dst);
+    assert_eq!(clock.encode_payload(), packet[5..].to_vec());
+
+    let mut invalid = packet.clone();
+    invalid[MONTH_IDX] = 13;
+    assert!(PanelClock::from_packet(&invalid).is_err());
+    assert!(PanelClock::from_packet(&packet[..10]).is_err());
+}
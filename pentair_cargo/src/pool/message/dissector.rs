@@ -0,0 +1,227 @@
+// Human readable breakdown of packets for the debug tools, decoded the same way the service
+// decodes them.
+
+use crate::pool::board::{self, Board};
+use crate::pool::message::chlorinator::{self, ChlorinatorMessage};
+use crate::pool::message::intellichem::{self, ChemistryState, FIRST_CHEM_ADDRESS};
+use crate::pool::message::pump_state::{self, PumpState, FIRST_PUMP_ADDRESS};
+use crate::pool::message::{
+    self, clock, equipment, heat, light, panel_config, schedule, PacketType, ProtocolPacket,
+    BROADCAST_ADDRESS, PANEL_ADDRESS,
+};
+
+// Actions of the panel and of the devices talking to it.
+const PANEL_ACTIONS: [(u8, &str); 38] = [
+    (message::ACK_ACTION, "acknowledgement"),
+    (message::STATUS_ACTION, "status"),
+    (clock::CLOCK_ACTION, "clock"),
+    (pump_state::PUMP_STATUS_ACTION, "pump status request"),
+    (heat::HEAT_STATUS_ACTION, "heat status"),
+    (panel_config::CUSTOM_NAME_ACTION, "custom name"),
+    (
+        panel_config::CIRCUIT_DEFINITION_ACTION,
+        "circuit definition",
+    ),
+    (schedule::SCHEDULE_ACTION, "schedule"),
+    (intellichem::CHEM_STATUS_ACTION, "IntelliChem status"),
+    (equipment::VALVE_CONFIG_ACTION, "valve configuration"),
+    (board::IC_CONFIG_ACTION, "IntelliCenter configuration"),
+    (panel_config::REMOTE_LAYOUT_ACTION, "remote layout"),
+    (equipment::SOLAR_CONFIG_ACTION, "solar configuration"),
+    (light::LIGHT_GROUP_ACTION, "light group"),
+    (light::LIGHT_COMMAND_ACTION, "light command"),
+    (clock::SET_CLOCK_ACTION, "set clock"),
+    (message::SET_CIRCUIT_ACTION, "set circuit"),
+    (heat::SET_HEAT_ACTION, "set heat"),
+    (schedule::SET_SCHEDULE_ACTION, "set schedule"),
+    (
+        equipment::SET_VALVE_CONFIG_ACTION,
+        "set valve configuration",
+    ),
+    (
+        equipment::SET_SOLAR_CONFIG_ACTION,
+        "set solar configuration",
+    ),
+    (board::IC_VERSION_ACTION, "IntelliCenter version"),
+    (light::SET_LIGHT_GROUP_ACTION, "set light group"),
+    (board::IC_SET_CIRCUITS_ACTION, "IntelliCenter set circuits"),
+    (clock::CLOCK_REQUEST, "clock request"),
+    (heat::HEAT_STATUS_REQUEST, "heat status request"),
+    (panel_config::CUSTOM_NAME_REQUEST, "custom name request"),
+    (
+        panel_config::CIRCUIT_DEFINITION_REQUEST,
+        "circuit definition request",
+    ),
+    (
+        board::IC_EXTENDED_STATUS_ACTION,
+        "IntelliCenter extended status",
+    ),
+    (schedule::SCHEDULE_REQUEST, "schedule request"),
+    (
+        intellichem::CHEM_STATUS_REQUEST,
+        "IntelliChem status request",
+    ),
+    (
+        equipment::VALVE_CONFIG_REQUEST,
+        "valve configuration request",
+    ),
+    (
+        board::IC_CONFIG_REQUEST,
+        "IntelliCenter configuration request",
+    ),
+    (panel_config::REMOTE_LAYOUT_REQUEST, "remote layout request"),
+    (
+        equipment::SOLAR_CONFIG_REQUEST,
+        "solar configuration request",
+    ),
+    (light::LIGHT_GROUP_REQUEST, "light group request"),
+    (message::VERSION_ACTION, "version"),
+    (message::VERSION_REQUEST, "version request"),
+];
+
+// Pumps reuse the low action codes.
+const PUMP_ACTIONS: [(u8, &str); 5] = [
+    (pump_state::SET_SPEED_ACTION, "set speed"),
+    (pump_state::REMOTE_CONTROL_ACTION, "remote control"),
+    (pump_state::SET_MODE_ACTION, "set mode"),
+    (pump_state::RUN_ACTION, "run"),
+    (pump_state::PUMP_STATUS_ACTION, "pump status"),
+];
+
+const CHLORINATOR_ACTIONS: [(u8, &str); 6] = [
+    (chlorinator::PROBE_ACTION, "probe"),
+    (chlorinator::PROBE_RESPONSE_ACTION, "probe response"),
+    (chlorinator::NAME_RESPONSE_ACTION, "name"),
+    (chlorinator::SET_OUTPUT_ACTION, "set output"),
+    (chlorinator::STATUS_ACTION, "status"),
+    (chlorinator::NAME_REQUEST_ACTION, "name request"),
+];
+
+fn lookup(names: &[(u8, &'static str)], action: u8) -> &'static str {
+    names
+        .iter()
+        .find(|(code, _)| *code == action)
+        .map_or("unknown", |(_, name)| name)
+}
+
+/// Bytes as space separated hex, `FF 00 FF A5`.
+pub fn hex_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn address_name(address: u8) -> String {
+    match address {
+        BROADCAST_ADDRESS => "broadcast".to_string(),
+        PANEL_ADDRESS => "panel".to_string(),
+        // Wireless and indoor controllers, remotes and this service.
+        0x20..=0x2F => "controller".to_string(),
+        _ if PumpState::is_pump_address(address) => {
+            format!("pump {}", address - FIRST_PUMP_ADDRESS + 1)
+        }
+        _ if ChemistryState::is_chem_address(address) => {
+            format!("IntelliChem {}", address - FIRST_CHEM_ADDRESS + 1)
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Name of the action, the same code means something else to the pumps.
+pub fn action_name(source: u8, dest: u8, action: u8) -> &'static str {
+    if PumpState::is_pump_address(source) || PumpState::is_pump_address(dest) {
+        lookup(&PUMP_ACTIONS, action)
+    } else {
+        lookup(&PANEL_ACTIONS, action)
+    }
+}
+
+/// The header fields, the payload and what the board decodes from a packet without the
+/// header and the checksum.
+pub fn describe(packet: &[u8], board: &dyn Board) -> String {
+    let fields = ProtocolPacket::new(packet);
+    let (source, dest, action) = (
+        fields.get_source(),
+        fields.get_destination(),
+        fields.get_action(),
+    );
+    let mut lines = vec![
+        format!(
+            "version {:#04x}, length {}",
+            fields.get_protocol_version(),
+            packet.get(4).copied().unwrap_or_default()
+        ),
+        format!("from    {:#04x} {}", source, address_name(source)),
+        format!("to      {:#04x} {}", dest, address_name(dest)),
+        format!(
+            "action  {:#04x} {}",
+            action,
+            action_name(source, dest, action)
+        ),
+        format!("payload {}", hex_string(fields.get_payload())),
+    ];
+    lines.push(match board.decode_packet(packet) {
+        Ok(ProtocolPacket {
+            decoded: PacketType::Unknown,
+            ..
+        }) => "not decoded".to_string(),
+        Ok(decoded) => format!("{:#?}", decoded.decoded),
+        Err(e) => format!("error: {}", e),
+    });
+    lines.join("\n")
+}
+
+/// Like `describe` for an IntelliChlor packet without the frame bytes and the checksum.
+pub fn describe_chlorinator(packet: &[u8]) -> String {
+    let dest = ChlorinatorMessage::get_destination(packet);
+    let action = packet.get(1).copied().unwrap_or_default();
+    let mut lines = vec![
+        format!("chlorinator to {:#04x}", dest),
+        format!(
+            "action  {:#04x} {}",
+            action,
+            lookup(&CHLORINATOR_ACTIONS, action)
+        ),
+        format!(
+            "payload {}",
+            hex_string(packet.get(2..).unwrap_or_default())
+        ),
+    ];
+    lines.push(match ChlorinatorMessage::decode(packet) {
+        Ok(ChlorinatorMessage::Unknown) => "not decoded".to_string(),
+        Ok(message) => format!("{:#?}", message),
+        Err(e) => format!("error: {}", e),
+    });
+    lines.join("\n")
+}
+
+#[cfg(test)]
+#[test]
+fn test_describe() {
+    use crate::pool::board::BoardKind;
+
+    let board = BoardKind::EasyTouch.board();
+    let description = describe(&[0x01, 0x10, 0x24, 0x86, 0x02, 0x06, 0x01], board.as_ref());
+    let lines: Vec<_> = description.lines().collect();
+    assert_eq!(lines[1], "from    0x24 controller");
+    assert_eq!(lines[2], "to      0x10 panel");
+    assert_eq!(lines[3], "action  0x86 set circuit");
+    assert_eq!(lines[4], "payload 06 01");
+    assert!(description.contains("circuit: 6"));
+
+    // The same action code is the speed to a pump.
+    assert_eq!(action_name(0x10, 0x60, 0x01), "set speed");
+    assert!(describe(&[0x01, 0x10], board.as_ref())
+        .ends_with("error: Framing error: Packet is too short for the header"));
+    assert!(describe_chlorinator(&[0x00, 0x12, 0x4F, 0x81]).contains("salt_ppm: 3950"));
+
+    // Every code has one name.
+    for table in [&PANEL_ACTIONS[..], &PUMP_ACTIONS, &CHLORINATOR_ACTIONS] {
+        let mut codes: Vec<_> = table.iter().map(|(code, _)| *code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), table.len());
+    }
+}
@@ -0,0 +1,220 @@
+// Equipment configuration of the panel: the valve assignments (0xDD asks, 0x1D answers, 0x9D
+// sets) and the solar and heat pump setup (0xE2 asks, 0x22 answers, 0xA2 sets).
+
+use serde::Serialize;
+
+pub const VALVE_CONFIG_ACTION: u8 = 0x1D;
+pub const SET_VALVE_CONFIG_ACTION: u8 = 0x9D;
+pub const VALVE_CONFIG_REQUEST: u8 = 0xDD;
+pub const SOLAR_CONFIG_ACTION: u8 = 0x22;
+pub const SET_SOLAR_CONFIG_ACTION: u8 = 0xA2;
+pub const SOLAR_CONFIG_REQUEST: u8 = 0xE2;
+
+// Valve payload: flags, three unused bytes and then the assignment of valve A, B and so on.
+const VALVE_FLAGS_IDX: usize = 0;
+const FIRST_VALVE_IDX: usize = 4;
+const VALVE_PAYLOAD_LEN: usize = 24;
+const INTAKE_RETURN_MASK: u8 = 0x01;
+const PUMP_OFF_MASK: u8 = 0x02;
+/// Valve assignments that are not circuits.
+const VALVE_SOLAR: u8 = 0x80;
+const VALVE_NOT_INSTALLED: u8 = 0xFF;
+
+// Solar payload: flags, the start and the stop temperature differences.
+const SOLAR_MASK: u8 = 0x01;
+const HEAT_PUMP_MASK: u8 = 0x02;
+const SOLAR_FREEZE_PROTECT_MASK: u8 = 0x04;
+const NIGHT_COOLING_MASK: u8 = 0x08;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValveAssignment {
+    NotUsed,
+    /// Turns with the circuit.
+    Circuit(u8),
+    /// Sends the water through the solar panels when solar heats.
+    Solar,
+}
+
+impl ValveAssignment {
+    fn from_code(code: u8) -> ValveAssignment {
+        match code {
+            0 => ValveAssignment::NotUsed,
+            VALVE_SOLAR => ValveAssignment::Solar,
+            circuit => ValveAssignment::Circuit(circuit),
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            ValveAssignment::NotUsed => 0,
+            ValveAssignment::Circuit(circuit) => *circuit,
+            ValveAssignment::Solar => VALVE_SOLAR,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Valve {
+    /// "A", "B" and so on as labeled on the panel.
+    pub name: String,
+    pub assignment: ValveAssignment,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ValveConfig {
+    /// The intake and return valves rotate to the spa when the spa circuit is on.
+    pub intake_return: bool,
+    /// The filter pump stops while the valves rotate.
+    pub pump_off_during_rotation: bool,
+    pub valves: Vec<Valve>,
+}
+
+impl ValveConfig {
+    pub fn from_payload(payload: &[u8]) -> Option<ValveConfig> {
+        if payload.len() < FIRST_VALVE_IDX + 2 {
+            return None;
+        }
+        let flags = payload[VALVE_FLAGS_IDX];
+        let valves = payload[FIRST_VALVE_IDX..]
+            .iter()
+            .take_while(|code| **code != VALVE_NOT_INSTALLED)
+            .zip('A'..='Z')
+            .map(|(code, name)| Valve {
+                name: name.to_string(),
+                assignment: ValveAssignment::from_code(*code),
+            })
+            .collect();
+        Some(ValveConfig {
+            intake_return: flags & INTAKE_RETURN_MASK != 0,
+            pump_off_during_rotation: flags & PUMP_OFF_MASK != 0,
+            valves,
+        })
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut payload = vec![VALVE_NOT_INSTALLED; VALVE_PAYLOAD_LEN];
+        payload[..FIRST_VALVE_IDX].fill(0);
+        if self.intake_return {
+            payload[VALVE_FLAGS_IDX] |= INTAKE_RETURN_MASK;
+        }
+        if self.pump_off_during_rotation {
+            payload[VALVE_FLAGS_IDX] |= PUMP_OFF_MASK;
+        }
+        for (code, valve) in payload[FIRST_VALVE_IDX..].iter_mut().zip(&self.valves) {
+            *code = valve.assignment.code();
+        }
+        payload
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaterType {
+    Gas,
+    Solar,
+    HeatPump,
+}
+
+/// Solar and heat pump share the heater relay, only one of them can be installed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SolarConfig {
+    pub solar: bool,
+    pub heat_pump: bool,
+    /// Solar runs to keep the water from freezing.
+    pub freeze_protect: bool,
+    /// Solar runs at night to cool the pool down to the setpoint.
+    pub night_cooling: bool,
+    /// Solar starts when the panels are this much warmer than the water and stops when the
+    /// difference drops to the stop one, in the temperature unit of the panel.
+    pub start_delta: u8,
+    pub stop_delta: u8,
+}
+
+impl SolarConfig {
+    pub fn from_payload(payload: &[u8]) -> Option<SolarConfig> {
+        match payload {
+            [flags, start_delta, stop_delta, ..] => Some(SolarConfig {
+                solar: flags & SOLAR_MASK != 0,
+                heat_pump: flags & HEAT_PUMP_MASK != 0,
+                freeze_protect: flags & SOLAR_FREEZE_PROTECT_MASK != 0,
+                night_cooling: flags & NIGHT_COOLING_MASK != 0,
+                start_delta: *start_delta,
+                stop_delta: *stop_delta,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let flags = [
+            (self.solar, SOLAR_MASK),
+            (self.heat_pump, HEAT_PUMP_MASK),
+            (self.freeze_protect, SOLAR_FREEZE_PROTECT_MASK),
+            (self.night_cooling, NIGHT_COOLING_MASK),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, mask)| flags | mask);
+        vec![flags, self.start_delta, self.stop_delta]
+    }
+
+    /// The heaters the panel can run, the gas heater is always there.
+    pub fn heaters(&self) -> Vec<HeaterType> {
+        let mut heaters = vec![HeaterType::Gas];
+        if self.solar {
+            heaters.push(HeaterType::Solar);
+        }
+        if self.heat_pump {
+            heaters.push(HeaterType::HeatPump);
+        }
+        heaters
+    }
+}
+
+/// What we learned about the equipment, None until the panel answered.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EquipmentConfig {
+    pub valves: Option<ValveConfig>,
+    pub solar: Option<SolarConfig>,
+    pub heaters: Vec<HeaterType>,
+}
+
+impl EquipmentConfig {
+    pub fn set_solar(&mut self, solar: SolarConfig) {
+        self.heaters = solar.heaters();
+        self.solar = Some(solar);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_equipment_config() {
+    // Valve A is the solar valve, valve B turns with the spa, the pump stops while they rotate.
+    let mut payload = vec![0x03, 0x00, 0x00, 0x00, 0x80, 0x01];
+    payload.resize(VALVE_PAYLOAD_LEN, VALVE_NOT_INSTALLED);
+    let valves = ValveConfig::from_payload(&payload).unwrap();
+    assert!(valves.intake_return && valves.pump_off_during_rotation);
+    assert_eq!(
+        valves.valves,
+        vec![
+            Valve {
+                name: "A".to_string(),
+                assignment: ValveAssignment::Solar
+            },
+            Valve {
+                name: "B".to_string(),
+                assignment: ValveAssignment::Circuit(1)
+            },
+        ]
+    );
+    assert_eq!(valves.encode_payload(), payload);
+    assert!(ValveConfig::from_payload(&payload[..5]).is_none());
+
+    let mut equipment = EquipmentConfig::default();
+    let solar = SolarConfig::from_payload(&[0x05, 0x06, 0x03]).unwrap();
+    assert!(solar.solar && solar.freeze_protect && !solar.heat_pump);
+    assert_eq!(solar.encode_payload(), vec![0x05, 0x06, 0x03]);
+    equipment.set_solar(solar);
+    assert_eq!(equipment.heaters, vec![HeaterType::Gas, HeaterType::Solar]);
+}
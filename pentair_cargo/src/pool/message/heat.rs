@@ -0,0 +1,166 @@
+// Heat settings of the pool and the spa: the heat status broadcast (0x08) and the set heat
+// command (0x88).
+
+use crate::error::PoolError;
+use crate::pool::message::PAYLOAD_IDX;
+use serde::{Deserialize, Serialize};
+
+pub const HEAT_STATUS_ACTION: u8 = 0x08;
+pub const SET_HEAT_ACTION: u8 = 0x88;
+pub const HEAT_STATUS_REQUEST: u8 = 0xC8;
+
+const POOL_TEMP_IDX: usize = PAYLOAD_IDX;
+const SPA_TEMP_IDX: usize = 6;
+const AIR_TEMP_IDX: usize = 7;
+const POOL_SETPOINT_IDX: usize = 8;
+const SPA_SETPOINT_IDX: usize = 9;
+const HEAT_MODE_IDX: usize = 10;
+const SOLAR_TEMP_IDX: usize = 14;
+/// Length of the heat status payload sent by EasyTouch.
+const HEAT_STATUS_PAYLOAD_LEN: usize = 13;
+
+// The pool mode is in the low two bits of the mode byte, the spa mode in the next two.
+const POOL_MODE_MASK: u8 = 0x03;
+const SPA_MODE_SHIFT: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Body {
+    Pool,
+    Spa,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeatMode {
+    Off,
+    Heater,
+    SolarPreferred,
+    Solar,
+}
+
+impl HeatMode {
+    fn from_bits(bits: u8) -> HeatMode {
+        match bits & POOL_MODE_MASK {
+            0 => HeatMode::Off,
+            1 => HeatMode::Heater,
+            2 => HeatMode::SolarPreferred,
+            _ => HeatMode::Solar,
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Setpoints in the units of the panel, see TemperatureUnit.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HeatSettings {
+    pub pool_setpoint: u8,
+    pub pool_mode: HeatMode,
+    pub spa_setpoint: u8,
+    pub spa_mode: HeatMode,
+}
+
+impl HeatSettings {
+    pub fn from_packet(packet: &[u8]) -> Result<HeatSettings, PoolError> {
+        if packet.len() <= HEAT_MODE_IDX {
+            return Err(PoolError::PayloadTooShort("Heat status"));
+        }
+        Ok(HeatSettings {
+            pool_setpoint: packet[POOL_SETPOINT_IDX],
+            pool_mode: HeatMode::from_bits(packet[HEAT_MODE_IDX]),
+            spa_setpoint: packet[SPA_SETPOINT_IDX],
+            spa_mode: HeatMode::from_bits(packet[HEAT_MODE_IDX] >> SPA_MODE_SHIFT),
+        })
+    }
+
+    /// Changes the setpoint and/or the mode of one body of water.
+    pub fn update(&mut self, body: Body, setpoint: Option<u8>, mode: Option<HeatMode>) {
+        let (current_setpoint, current_mode) = match body {
+            Body::Pool => (&mut self.pool_setpoint, &mut self.pool_mode),
+            Body::Spa => (&mut self.spa_setpoint, &mut self.spa_mode),
+        };
+        if let Some(setpoint) = setpoint {
+            *current_setpoint = setpoint;
+        }
+        if let Some(mode) = mode {
+            *current_mode = mode;
+        }
+    }
+
+    fn mode_bits(&self) -> u8 {
+        self.pool_mode.bits() | (self.spa_mode.bits() << SPA_MODE_SHIFT)
+    }
+
+    /// Payload of the set heat command, the last byte is the cooling setpoint of
+    /// IntelliTouch, unused here.
+    pub fn encode_set_payload(&self) -> Vec<u8> {
+        vec![self.pool_setpoint, self.spa_setpoint, self.mode_bits(), 0]
+    }
+
+    pub fn from_set_payload(payload: &[u8]) -> Option<HeatSettings> {
+        match payload {
+            [pool_setpoint, spa_setpoint, modes, ..] => Some(HeatSettings {
+                pool_setpoint: *pool_setpoint,
+                pool_mode: HeatMode::from_bits(*modes),
+                spa_setpoint: *spa_setpoint,
+                spa_mode: HeatMode::from_bits(*modes >> SPA_MODE_SHIFT),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Builds the heat status payload as the panel sends it, used by the simulator.
+    pub fn encode_payload(
+        &self,
+        pool_temp: u8,
+        spa_temp: u8,
+        air_temp: u8,
+        solar_temp: u8,
+    ) -> Vec<u8> {
+        let mut payload = vec![0; HEAT_STATUS_PAYLOAD_LEN];
+        let mut set = |idx: usize, value: u8| payload[idx - PAYLOAD_IDX] = value;
+        set(POOL_TEMP_IDX, pool_temp);
+        set(SPA_TEMP_IDX, spa_temp);
+        set(AIR_TEMP_IDX, air_temp);
+        set(POOL_SETPOINT_IDX, self.pool_setpoint);
+        set(SPA_SETPOINT_IDX, self.spa_setpoint);
+        set(HEAT_MODE_IDX, self.mode_bits());
+        set(SOLAR_TEMP_IDX, solar_temp);
+        payload
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_heat_settings() {
+    // Pool at 82 on solar preferred, spa at 102 on the heater.
+    let packet = vec![
+        0x01, 0x0F, 0x10, 0x08, 0x0D, 0x52, 0x52, 0x48, 0x52, 0x66, 0x06, 0x00, 0x00, 0x00, 0x53,
+        0x00, 0x00, 0x00,
+    ];
+    let mut settings = HeatSettings::from_packet(&packet).unwrap();
+    assert_eq!(
+        settings,
+        HeatSettings {
+            pool_setpoint: 82,
+            pool_mode: HeatMode::SolarPreferred,
+            spa_setpoint: 102,
+            spa_mode: HeatMode::Heater,
+        }
+    );
+    assert_eq!(
+        settings.encode_payload(0x52, 0x52, 0x48, 0x53),
+        packet[5..].to_vec()
+    );
+
+    settings.update(Body::Spa, Some(100), Some(HeatMode::Off));
+    assert_eq!(settings.encode_set_payload(), vec![82, 100, 0x02, 0]);
+    assert_eq!(
+        HeatSettings::from_set_payload(&settings.encode_set_payload()),
+        Some(settings)
+    );
+    assert!(HeatSettings::from_packet(&packet[..8]).is_err());
+}
@@ -0,0 +1,232 @@
+// IntelliChem chemistry controllers: the panel asks with 0xD2 and the controller answers with
+// its status (0x12), the readings, setpoints, tanks, alarms and what it is dosing.
+
+use crate::error::PoolError;
+use crate::pool::message::PAYLOAD_IDX;
+use serde::Serialize;
+
+/// Addresses of IntelliChem controllers on the bus, the first one is 0x90.
+pub const FIRST_CHEM_ADDRESS: u8 = 0x90;
+pub const LAST_CHEM_ADDRESS: u8 = 0x9F;
+
+pub const CHEM_STATUS_ACTION: u8 = 0x12;
+pub const CHEM_STATUS_REQUEST: u8 = 0xD2;
+
+const SRC_IDX: usize = 2;
+const PH_IDX: usize = PAYLOAD_IDX; // Two bytes, big endian, in hundredths.
+const ORP_IDX: usize = 7; // Two bytes, big endian, in mV.
+const PH_SETPOINT_IDX: usize = 9;
+const ORP_SETPOINT_IDX: usize = 11;
+const PH_TANK_IDX: usize = 25;
+const ORP_TANK_IDX: usize = 26;
+const LSI_IDX: usize = 27;
+const CALCIUM_IDX: usize = 28; // Two bytes, big endian.
+const CYANURIC_ACID_IDX: usize = 31;
+const ALKALINITY_IDX: usize = 32; // Two bytes, big endian.
+const SALT_IDX: usize = 34; // In 50 ppm.
+const TEMPERATURE_IDX: usize = 36;
+const ALARMS_IDX: usize = 37;
+const WARNINGS_IDX: usize = 38;
+const DOSING_IDX: usize = 39;
+/// Length of the status payload sent by IntelliChem.
+const CHEM_STATUS_PAYLOAD_LEN: usize = 41;
+
+const SALT_UNIT: u16 = 50;
+/// Tanks report 1 to 7, 0 when there is no tank.
+const TANK_FULL: u8 = 7;
+
+const ALARMS: [(u8, &str); 8] = [
+    (0x01, "no flow"),
+    (0x02, "pH high"),
+    (0x04, "pH low"),
+    (0x08, "ORP high"),
+    (0x10, "ORP low"),
+    (0x20, "pH tank empty"),
+    (0x40, "ORP tank empty"),
+    (0x80, "probe fault"),
+];
+const WARNINGS: [(u8, &str); 5] = [
+    (0x01, "pH lockout"),
+    (0x02, "pH daily limit reached"),
+    (0x04, "ORP daily limit reached"),
+    (0x08, "invalid setup"),
+    (0x10, "chlorinator communication error"),
+];
+const FLOW_ALARM: u8 = 0x01;
+// The dosing state of pH is in bits 4-5 of the dosing byte, of ORP in bits 6-7.
+const PH_DOSING_SHIFT: u8 = 4;
+const ORP_DOSING_SHIFT: u8 = 6;
+const DOSING_MASK: u8 = 0x03;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DosingState {
+    Dosing,
+    /// Waits for the dose to mix before measuring again.
+    Mixing,
+    Monitoring,
+    Off,
+}
+
+impl DosingState {
+    fn from_bits(bits: u8) -> DosingState {
+        match bits & DOSING_MASK {
+            0 => DosingState::Dosing,
+            1 => DosingState::Mixing,
+            2 => DosingState::Monitoring,
+            _ => DosingState::Off,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChemistryState {
+    pub address: u8,
+    pub ph: f32,
+    /// Oxidation reduction potential in mV, how well the water sanitizes.
+    pub orp: u16,
+    pub ph_setpoint: f32,
+    pub orp_setpoint: u16,
+    /// Acid and chlorine tank levels from 0, empty or no tank, to 6, full.
+    pub ph_tank: u8,
+    pub orp_tank: u8,
+    /// Langelier saturation index, below zero the water is corrosive, above it scales.
+    pub lsi: f32,
+    pub calcium_hardness: u16,
+    pub cyanuric_acid: u8,
+    pub alkalinity: u16,
+    pub salt_ppm: u16,
+    pub temperature: u8,
+    pub flow_alarm: bool,
+    pub alarms: Vec<String>,
+    pub warnings: Vec<String>,
+    pub ph_dosing: DosingState,
+    pub orp_dosing: DosingState,
+}
+
+fn read_u16(packet: &[u8], idx: usize) -> u16 {
+    u16::from_be_bytes([packet[idx], packet[idx + 1]])
+}
+
+fn flags(bits: u8, names: &[(u8, &str)]) -> Vec<String> {
+    names
+        .iter()
+        .filter(|(mask, _)| bits & mask != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+// The index is sent as a signed byte in hundredths.
+fn decode_lsi(value: u8) -> f32 {
+    value as i8 as f32 / 100.0
+}
+
+fn tank_level(value: u8) -> u8 {
+    value.saturating_sub(1).min(TANK_FULL - 1)
+}
+
+impl ChemistryState {
+    pub fn is_chem_address(address: u8) -> bool {
+        (FIRST_CHEM_ADDRESS..=LAST_CHEM_ADDRESS).contains(&address)
+    }
+
+    pub fn from_packet(packet: &[u8]) -> Result<ChemistryState, PoolError> {
+        if packet.len() < PAYLOAD_IDX + CHEM_STATUS_PAYLOAD_LEN {
+            return Err(PoolError::PayloadTooShort("IntelliChem status"));
+        }
+        let dosing = packet[DOSING_IDX];
+        Ok(ChemistryState {
+            address: packet[SRC_IDX],
+            ph: read_u16(packet, PH_IDX) as f32 / 100.0,
+            orp: read_u16(packet, ORP_IDX),
+            ph_setpoint: read_u16(packet, PH_SETPOINT_IDX) as f32 / 100.0,
+            orp_setpoint: read_u16(packet, ORP_SETPOINT_IDX),
+            ph_tank: tank_level(packet[PH_TANK_IDX]),
+            orp_tank: tank_level(packet[ORP_TANK_IDX]),
+            lsi: decode_lsi(packet[LSI_IDX]),
+            calcium_hardness: read_u16(packet, CALCIUM_IDX),
+            cyanuric_acid: packet[CYANURIC_ACID_IDX],
+            alkalinity: read_u16(packet, ALKALINITY_IDX),
+            salt_ppm: packet[SALT_IDX] as u16 * SALT_UNIT,
+            temperature: packet[TEMPERATURE_IDX],
+            flow_alarm: packet[ALARMS_IDX] & FLOW_ALARM != 0,
+            alarms: flags(packet[ALARMS_IDX], &ALARMS),
+            warnings: flags(packet[WARNINGS_IDX], &WARNINGS),
+            ph_dosing: DosingState::from_bits(dosing >> PH_DOSING_SHIFT),
+            orp_dosing: DosingState::from_bits(dosing >> ORP_DOSING_SHIFT),
+        })
+    }
+
+    /// Builds the status payload as IntelliChem sends it, used by the simulator. Alarms and
+    /// warnings are not encoded but the flow alarm.
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut payload = vec![0; CHEM_STATUS_PAYLOAD_LEN];
+        let mut set = |idx: usize, value: u8| payload[idx - PAYLOAD_IDX] = value;
+        for (idx, value) in [
+            (PH_IDX, (self.ph * 100.0).round() as u16),
+            (ORP_IDX, self.orp),
+            (PH_SETPOINT_IDX, (self.ph_setpoint * 100.0).round() as u16),
+            (ORP_SETPOINT_IDX, self.orp_setpoint),
+            (CALCIUM_IDX, self.calcium_hardness),
+            (ALKALINITY_IDX, self.alkalinity),
+        ] {
+            let [hi, lo] = value.to_be_bytes();
+            set(idx, hi);
+            set(idx + 1, lo);
+        }
+        set(PH_TANK_IDX, self.ph_tank + 1);
+        set(ORP_TANK_IDX, self.orp_tank + 1);
+        set(LSI_IDX, (self.lsi * 100.0).round() as i8 as u8);
+        set(CYANURIC_ACID_IDX, self.cyanuric_acid);
+        set(SALT_IDX, (self.salt_ppm / SALT_UNIT) as u8);
+        set(TEMPERATURE_IDX, self.temperature);
+        set(ALARMS_IDX, self.flow_alarm as u8 * FLOW_ALARM);
+        set(
+            DOSING_IDX,
+            (self.ph_dosing as u8) << PH_DOSING_SHIFT | (self.orp_dosing as u8) << ORP_DOSING_SHIFT,
+        );
+        payload
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_chemistry_state() {
+    // pH 7.40 of 7.50 while dosing acid, ORP 690 of 700 while mixing, LSI -0.12.
+    let mut packet = vec![0x00, 0x10, 0x90, 0x12, 0x29];
+    packet.extend([0x02, 0xE4, 0x02, 0xB2, 0x02, 0xEE, 0x02, 0xBC]);
+    packet.resize(PAYLOAD_IDX + CHEM_STATUS_PAYLOAD_LEN, 0);
+    packet[PH_TANK_IDX] = 5;
+    packet[ORP_TANK_IDX] = 7;
+    packet[LSI_IDX] = 0xF4;
+    packet[CALCIUM_IDX + 1] = 250;
+    packet[CYANURIC_ACID_IDX] = 40;
+    packet[ALKALINITY_IDX + 1] = 90;
+    packet[SALT_IDX] = 64;
+    packet[TEMPERATURE_IDX] = 82;
+    packet[ALARMS_IDX] = 0x21;
+    packet[DOSING_IDX] = 0x40;
+    let state = ChemistryState::from_packet(&packet).unwrap();
+    assert_eq!(state.ph, 7.4);
+    assert_eq!(state.orp, 690);
+    assert_eq!(state.ph_setpoint, 7.5);
+    assert_eq!((state.ph_tank, state.orp_tank), (4, 6));
+    assert_eq!(state.lsi, -0.12);
+    assert_eq!(state.salt_ppm, 3200);
+    assert!(state.flow_alarm);
+    assert_eq!(state.alarms, vec!["no flow", "pH tank empty"]);
+    assert_eq!(
+        (state.ph_dosing, state.orp_dosing),
+        (DosingState::Dosing, DosingState::Mixing)
+    );
+
+    // The simulator encoding reads back the same, the alarms aside.
+    let mut encoded = packet[..PAYLOAD_IDX].to_vec();
+    encoded.extend(state.encode_payload());
+    let decoded = ChemistryState::from_packet(&encoded).unwrap();
+    assert_eq!(decoded.alarms, vec!["no flow"]);
+    assert_eq!(decoded.ph, state.ph);
+    assert_eq!(decoded.lsi, state.lsi);
+    assert_eq!(decoded.salt_ppm, state.salt_ppm);
+    assert!(ChemistryState::from_packet(&packet[..20]).is_err());
+}
@@ -0,0 +1,213 @@
+// IntelliBrite color lights: 0x60 runs a theme or a color mode on all of them, the light group
+// (0xE7 asks, 0x27 answers, 0xA7 sets) has the position, color and swim delay of each light.
+
+use serde::{Deserialize, Serialize};
+
+pub const LIGHT_COMMAND_ACTION: u8 = 0x60;
+pub const LIGHT_GROUP_ACTION: u8 = 0x27;
+pub const SET_LIGHT_GROUP_ACTION: u8 = 0xA7;
+pub const LIGHT_GROUP_REQUEST: u8 = 0xE7;
+
+/// Lights in the group of an EasyTouch, 4 bytes each: circuit, position and color, swim
+/// delay, unused.
+pub const MAX_GROUP_LIGHTS: usize = 8;
+const LIGHT_ENTRY_LEN: usize = 4;
+const POSITION_SHIFT: u8 = 4;
+const COLOR_MASK: u8 = 0x0F;
+const MAX_SWIM_DELAY: u8 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightTheme {
+    Off,
+    On,
+    /// All lights change colors together.
+    ColorSync,
+    /// The colors move from light to light with the swim delay.
+    ColorSwim,
+    /// Every light shows the color of the group configuration.
+    ColorSet,
+    Party,
+    Romance,
+    Caribbean,
+    American,
+    CaliforniaSunset,
+    Royal,
+    /// Remembers the current colors for Recall.
+    Save,
+    Recall,
+    Blue,
+    Green,
+    Red,
+    White,
+    Magenta,
+}
+
+const THEME_CODES: [(LightTheme, u8); 18] = [
+    (LightTheme::Off, 0),
+    (LightTheme::On, 1),
+    (LightTheme::ColorSync, 128),
+    (LightTheme::ColorSwim, 144),
+    (LightTheme::ColorSet, 160),
+    (LightTheme::Party, 177),
+    (LightTheme::Romance, 178),
+    (LightTheme::Caribbean, 179),
+    (LightTheme::American, 180),
+    (LightTheme::CaliforniaSunset, 181),
+    (LightTheme::Royal, 182),
+    (LightTheme::Save, 190),
+    (LightTheme::Recall, 191),
+    (LightTheme::Blue, 193),
+    (LightTheme::Green, 194),
+    (LightTheme::Red, 195),
+    (LightTheme::White, 196),
+    (LightTheme::Magenta, 197),
+];
+
+impl LightTheme {
+    pub fn from_code(code: u8) -> Option<LightTheme> {
+        THEME_CODES
+            .iter()
+            .find(|(_, theme_code)| *theme_code == code)
+            .map(|(theme, _)| *theme)
+    }
+
+    pub fn code(&self) -> u8 {
+        THEME_CODES
+            .iter()
+            .find(|(theme, _)| theme == self)
+            .map(|(_, code)| *code)
+            .unwrap_or_default()
+    }
+
+    /// Payload of 0x60, the second byte is unused.
+    pub fn encode_payload(&self) -> Vec<u8> {
+        vec![self.code(), 0]
+    }
+}
+
+/// Colors of the color set mode.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightColor {
+    White,
+    LightGreen,
+    Green,
+    Cyan,
+    Blue,
+    Lavender,
+    Magenta,
+    LightMagenta,
+}
+
+impl LightColor {
+    const ALL: [LightColor; 8] = [
+        LightColor::White,
+        LightColor::LightGreen,
+        LightColor::Green,
+        LightColor::Cyan,
+        LightColor::Blue,
+        LightColor::Lavender,
+        LightColor::Magenta,
+        LightColor::LightMagenta,
+    ];
+
+    // The panel uses even codes only.
+    fn from_code(code: u8) -> LightColor {
+        LightColor::ALL[(code as usize / 2) % LightColor::ALL.len()]
+    }
+
+    fn code(&self) -> u8 {
+        *self as u8 * 2
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GroupLight {
+    pub circuit: u8,
+    /// Order of the light in color swim, from 1.
+    pub position: u8,
+    pub color: LightColor,
+    /// Seconds before the next light takes the color in color swim.
+    pub swim_delay: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LightGroup {
+    pub lights: Vec<GroupLight>,
+}
+
+impl LightGroup {
+    /// Decodes the payload of 0x27 and 0xA7, the slots without a circuit are skipped.
+    pub fn from_payload(payload: &[u8]) -> Option<LightGroup> {
+        if payload.len() < LIGHT_ENTRY_LEN {
+            return None;
+        }
+        let lights = payload
+            .chunks_exact(LIGHT_ENTRY_LEN)
+            .filter(|entry| entry[0] != 0)
+            .map(|entry| GroupLight {
+                circuit: entry[0],
+                position: (entry[1] >> POSITION_SHIFT) + 1,
+                color: LightColor::from_code(entry[1] & COLOR_MASK),
+                swim_delay: entry[2] >> 1,
+            })
+            .collect();
+        Some(LightGroup { lights })
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.lights.len() <= MAX_GROUP_LIGHTS
+            && self.lights.iter().all(|light| {
+                light.circuit != 0
+                    && (1..=MAX_GROUP_LIGHTS as u8).contains(&light.position)
+                    && light.swim_delay <= MAX_SWIM_DELAY
+            })
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut payload = vec![0; MAX_GROUP_LIGHTS * LIGHT_ENTRY_LEN];
+        for (entry, light) in payload.chunks_exact_mut(LIGHT_ENTRY_LEN).zip(&self.lights) {
+            entry[0] = light.circuit;
+            entry[1] = ((light.position - 1) << POSITION_SHIFT) | light.color.code();
+            entry[2] = light.swim_delay << 1;
+        }
+        payload
+    }
+}
+
+/// What we know of the lights: the last theme the panel took and the group.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LightState {
+    pub theme: Option<LightTheme>,
+    pub group: Option<LightGroup>,
+}
+
+#[cfg(test)]
+#[test]
+fn test_light_messages() {
+    assert_eq!(LightTheme::from_code(179), Some(LightTheme::Caribbean));
+    assert_eq!(LightTheme::ColorSwim.encode_payload(), vec![144, 0]);
+    assert_eq!(LightTheme::from_code(2), None);
+
+    // The pool light is second in the swim and blue, the spa light first and white.
+    let mut payload = vec![0x02, 0x18, 0x0A, 0x00, 0x01, 0x00, 0x04, 0x00];
+    payload.resize(MAX_GROUP_LIGHTS * LIGHT_ENTRY_LEN, 0);
+    let group = LightGroup::from_payload(&payload).unwrap();
+    assert_eq!(
+        group.lights[0],
+        GroupLight {
+            circuit: 2,
+            position: 2,
+            color: LightColor::Blue,
+            swim_delay: 5
+        }
+    );
+    assert_eq!(group.lights.len(), 2);
+    assert!(group.is_valid());
+    assert_eq!(group.encode_payload(), payload);
+
+    let mut invalid = group.clone();
+    invalid.lights[1].position = 0;
+    assert!(!invalid.is_valid());
+}
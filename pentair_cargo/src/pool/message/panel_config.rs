@@ -0,0 +1,236 @@
+// Configuration the panel reports when asked: circuit definitions (0xCB -> 0x0B), custom
+// names (0xCA -> 0x0A) and the remote layout (0xE1 -> 0x21).
+
+use crate::pool::message::circuit::CircuitFunction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const CIRCUIT_DEFINITION_ACTION: u8 = 0x0B;
+pub const CIRCUIT_DEFINITION_REQUEST: u8 = 0xCB;
+pub const CUSTOM_NAME_ACTION: u8 = 0x0A;
+pub const CUSTOM_NAME_REQUEST: u8 = 0xCA;
+pub const REMOTE_LAYOUT_ACTION: u8 = 0x21;
+pub const REMOTE_LAYOUT_REQUEST: u8 = 0xE1;
+
+/// Circuits and custom names we ask an EasyTouch for.
+pub const MAX_CIRCUITS: u8 = 20;
+pub const MAX_CUSTOM_NAMES: u8 = 10;
+
+const FUNCTION_MASK: u8 = 0x3F;
+const FREEZE_PROTECT_MASK: u8 = 0x40;
+/// Name ids from this one on are custom names.
+const FIRST_CUSTOM_NAME_ID: u8 = 200;
+const CUSTOM_NAME_LEN: usize = 11;
+
+/// Built-in circuit names of EasyTouch, indexed by the name id.
+const CIRCUIT_NAMES: [&str; 102] = [
+    "NOT USED",
+    "AERATOR",
+    "AIR BLOWER",
+    "AUX 1",
+    "AUX 2",
+    "AUX 3",
+    "AUX 4",
+    "AUX 5",
+    "AUX 6",
+    "AUX 7",
+    "AUX 8",
+    "AUX 9",
+    "AUX 10",
+    "BACKWASH",
+    "BACK LIGHT",
+    "BBQ LIGHT",
+    "BEACH LIGHT",
+    "BOOSTER PUMP",
+    "BUG LIGHT",
+    "CABANA LTS",
+    "CHEM. FEEDER",
+    "CHLORINATOR",
+    "CLEANER",
+    "COLOR WHEEL",
+    "DECK LIGHT",
+    "DRAIN LINE",
+    "DRIVE LIGHT",
+    "EDGE PUMP",
+    "ENTRY LIGHT",
+    "FAN",
+    "FIBER OPTIC",
+    "FIBER WORKS",
+    "FILL LINE",
+    "FLOOR CLNR",
+    "FOGGER",
+    "FOUNTAIN",
+    "FOUNTAIN 1",
+    "FOUNTAIN 2",
+    "FOUNTAIN 3",
+    "FOUNTAINS",
+    "FRONT LIGHT",
+    "GARDEN LTS",
+    "GAZEBO LTS",
+    "HIGH SPEED",
+    "HI-TEMP",
+    "HOUSE LIGHT",
+    "JETS",
+    "LIGHTS",
+    "LOW SPEED",
+    "LO-TEMP",
+    "MALIBU LTS",
+    "MIST",
+    "MUSIC",
+    "NOT USED",
+    "OZONATOR",
+    "PATH LIGHTS",
+    "PATIO LTS",
+    "PERIMETER L",
+    "PG2000",
+    "POND LIGHT",
+    "POOL PUMP",
+    "POOL",
+    "POOL HIGH",
+    "POOL LIGHT",
+    "POOL LOW",
+    "SAM",
+    "POOL SAM 1",
+    "POOL SAM 2",
+    "POOL SAM 3",
+    "SECURITY LT",
+    "SLIDE",
+    "SOLAR",
+    "SPA",
+    "SPA HIGH",
+    "SPA LIGHT",
+    "SPA LOW",
+    "SPA SAL",
+    "SPA SAM",
+    "SPA WTRFLL",
+    "SPILLWAY",
+    "SPRINKLERS",
+    "STREAM",
+    "STATUE LT",
+    "SWIM JETS",
+    "WTR FEATURE",
+    "WTR FEAT LT",
+    "WATERFALL",
+    "WATERFALL 1",
+    "WATERFALL 2",
+    "WATERFALL 3",
+    "WHIRLPOOL",
+    "WTRFL LGHT",
+    "YARD LIGHT",
+    "AUX EXTRA",
+    "FEATURE 1",
+    "FEATURE 2",
+    "FEATURE 3",
+    "FEATURE 4",
+    "FEATURE 5",
+    "FEATURE 6",
+    "FEATURE 7",
+    "FEATURE 8",
+];
+
+/// A circuit as defined on the panel.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CircuitDefinition {
+    pub number: u8,
+    pub function: CircuitFunction,
+    pub freeze_protect: bool,
+    /// Index into the built-in names, or a custom name from FIRST_CUSTOM_NAME_ID on.
+    pub name_id: u8,
+}
+
+impl CircuitDefinition {
+    pub fn from_payload(payload: &[u8]) -> Option<CircuitDefinition> {
+        match payload {
+            [number, function, name_id, ..] => Some(CircuitDefinition {
+                number: *number,
+                function: CircuitFunction::from_code(function & FUNCTION_MASK),
+                freeze_protect: function & FREEZE_PROTECT_MASK != 0,
+                name_id: *name_id,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The payload of the definition as the panel sends it, used by the simulator.
+    pub fn encode_payload(
+        number: u8,
+        function_code: u8,
+        freeze_protect: bool,
+        name_id: u8,
+    ) -> Vec<u8> {
+        let freeze = if freeze_protect {
+            FREEZE_PROTECT_MASK
+        } else {
+            0
+        };
+        vec![number, function_code | freeze, name_id, 0, 0]
+    }
+}
+
+/// Custom name from the payload of 0x0A: the index and the name padded with spaces or zeros.
+pub fn decode_custom_name(payload: &[u8]) -> Option<(u8, String)> {
+    let (index, name) = payload.split_first()?;
+    let name = String::from_utf8_lossy(&name[..name.len().min(CUSTOM_NAME_LEN)])
+        .trim_end_matches(['\0', ' '])
+        .to_string();
+    Some((*index, name))
+}
+
+pub fn encode_custom_name(index: u8, name: &str) -> Vec<u8> {
+    let mut payload = vec![index];
+    payload.extend(name.bytes().take(CUSTOM_NAME_LEN));
+    payload.resize(CUSTOM_NAME_LEN + 1, 0);
+    payload
+}
+
+/// What we learned about the panel configuration.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PanelConfig {
+    pub circuits: BTreeMap<u8, CircuitDefinition>,
+    pub custom_names: BTreeMap<u8, String>,
+    /// Circuits on the buttons of the remotes, by the remote id.
+    pub remotes: BTreeMap<u8, Vec<u8>>,
+}
+
+impl PanelConfig {
+    /// The name of a circuit on the panel, None until the definition and the custom name
+    /// it refers to are known.
+    pub fn circuit_name(&self, number: u8) -> Option<String> {
+        let definition = self.circuits.get(&number)?;
+        match definition.name_id {
+            id if id >= FIRST_CUSTOM_NAME_ID => {
+                self.custom_names.get(&(id - FIRST_CUSTOM_NAME_ID)).cloned()
+            }
+            id => CIRCUIT_NAMES.get(id as usize).map(|name| name.to_string()),
+        }
+    }
+
+    pub fn set_remote_layout(&mut self, payload: &[u8]) {
+        if let Some((remote, buttons)) = payload.split_first() {
+            self.remotes.insert(*remote, buttons.to_vec());
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_panel_config() {
+    let mut config = PanelConfig::default();
+    // Circuit 6 is the pool, circuit 3 has the custom name 1.
+    let pool = CircuitDefinition::from_payload(&[0x06, 0x02, 61, 0x00, 0x00]).unwrap();
+    assert_eq!(pool.function, CircuitFunction::Pool);
+    config.circuits.insert(6, pool);
+    let slide =
+        CircuitDefinition::from_payload(&CircuitDefinition::encode_payload(3, 0, true, 201))
+            .unwrap();
+    assert!(slide.freeze_protect);
+    config.circuits.insert(3, slide);
+
+    assert_eq!(config.circuit_name(6), Some("POOL".to_string()));
+    assert_eq!(config.circuit_name(3), None);
+    let (index, name) = decode_custom_name(&encode_custom_name(1, "WATERSLIDE")).unwrap();
+    config.custom_names.insert(index, name);
+    assert_eq!(config.circuit_name(3), Some("WATERSLIDE".to_string()));
+    assert_eq!(config.circuit_name(4), None);
+    assert!(CircuitDefinition::from_payload(&[0x06, 0x02]).is_none());
+}
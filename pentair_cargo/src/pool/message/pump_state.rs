@@ -0,0 +1,243 @@
+use crate::error::PoolError;
+use crate::pool::message::{ProtocolPacket, PAYLOAD_IDX};
+use serde::{Deserialize, Serialize};
+
+/// Addresses of IntelliFlo pumps on the bus, pump 1 is 0x60.
+pub const FIRST_PUMP_ADDRESS: u8 = 0x60;
+pub const LAST_PUMP_ADDRESS: u8 = 0x6F;
+
+const SRC_IDX: usize = 2;
+const RUN_IDX: usize = PAYLOAD_IDX;
+const MODE_IDX: usize = 6;
+const DRIVE_STATE_IDX: usize = 7;
+const WATTS_IDX: usize = 8; // Two bytes, big endian.
+const RPM_IDX: usize = 10; // Two bytes, big endian.
+const GPM_IDX: usize = 12;
+const PERCENT_IDX: usize = 13;
+const ERROR_IDX: usize = 15;
+const TIMER_IDX: usize = 17;
+const HOUR_IDX: usize = 18;
+const MINUTE_IDX: usize = 19;
+/// Length of the status payload sent by the pump.
+const STATUS_PAYLOAD_LEN: usize = 15;
+
+const RUNNING: u8 = 0x0A;
+const STOPPED: u8 = 0x04;
+
+/// Actions of the commands a pump accepts. The pump replies with the same action.
+pub const SET_SPEED_ACTION: u8 = 0x01;
+pub const REMOTE_CONTROL_ACTION: u8 = 0x04;
+pub const SET_MODE_ACTION: u8 = 0x05;
+pub const RUN_ACTION: u8 = 0x06;
+/// The panel asks for the status with the same action the pump answers with.
+pub const PUMP_STATUS_ACTION: u8 = 0x07;
+
+const REMOTE_CONTROL_ON: u8 = 0xFF;
+const REMOTE_CONTROL_OFF: u8 = 0x00;
+// Registers written by the set speed command.
+const RPM_REGISTER: [u8; 2] = [0x02, 0xC4];
+const GPM_REGISTER: [u8; 2] = [0x02, 0xE4];
+const PROGRAM_REGISTER: [u8; 2] = [0x03, 0x21];
+
+const MIN_RPM: u16 = 450;
+const MAX_RPM: u16 = 3450;
+const MIN_GPM: u8 = 15;
+const MAX_GPM: u8 = 130;
+const PROGRAMS: u8 = 4;
+
+/// What we can ask an IntelliFlo pump to do when we take it over from the panel.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum PumpCommand {
+    Rpm {
+        value: u16,
+    },
+    Gpm {
+        value: u8,
+    },
+    /// One of the external programs 1-4.
+    Program {
+        value: u8,
+    },
+    Stop,
+    /// Gives the control back to the panel.
+    Release,
+}
+
+impl PumpCommand {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            PumpCommand::Rpm { value } => (MIN_RPM..=MAX_RPM).contains(&value),
+            PumpCommand::Gpm { value } => (MIN_GPM..=MAX_GPM).contains(&value),
+            PumpCommand::Program { value } => (1..=PROGRAMS).contains(&value),
+            PumpCommand::Stop | PumpCommand::Release => true,
+        }
+    }
+
+    /// The packets that carry out the command, in the order they have to be sent, with
+    /// the action of the reply that acknowledges each of them.
+    pub fn encode(&self, pump: u8, source: u8) -> Result<Vec<(Vec<u8>, u8)>, PoolError> {
+        let packet = |action: u8, payload: &[u8]| {
+            ProtocolPacket::encode_packet(0x00, pump, source, action, payload)
+                .map(|frame| (frame, action))
+        };
+        let set_speed = |register: [u8; 2], value: [u8; 2]| {
+            packet(
+                SET_SPEED_ACTION,
+                &[register[0], register[1], value[0], value[1]],
+            )
+        };
+        let take_control = packet(REMOTE_CONTROL_ACTION, &[REMOTE_CONTROL_ON])?;
+        let run = packet(RUN_ACTION, &[RUNNING])?;
+        Ok(match *self {
+            PumpCommand::Rpm { value } => {
+                vec![
+                    take_control,
+                    set_speed(RPM_REGISTER, value.to_be_bytes())?,
+                    run,
+                ]
+            }
+            PumpCommand::Gpm { value } => {
+                vec![take_control, set_speed(GPM_REGISTER, [0, value])?, run]
+            }
+            // The pump expects the program number multiplied by 8.
+            PumpCommand::Program { value } => {
+                vec![
+                    take_control,
+                    set_speed(PROGRAM_REGISTER, [0, value * 8])?,
+                    run,
+                ]
+            }
+            PumpCommand::Stop => vec![take_control, packet(RUN_ACTION, &[STOPPED])?],
+            PumpCommand::Release => vec![packet(REMOTE_CONTROL_ACTION, &[REMOTE_CONTROL_OFF])?],
+        })
+    }
+}
+
+/// The decoded pump status (0x07) sent by an IntelliFlo pump.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PumpState {
+    /// Address of the pump on the bus.
+    pub address: u8,
+    pub running: bool,
+    /// Operation mode: filter, manual, backwash, external programs etc.
+    pub mode: u8,
+    pub drive_state: u8,
+    pub watts: u16,
+    pub rpm: u16,
+    pub gpm: u8,
+    pub percent: u8,
+    pub error: u8,
+    /// Minutes left on the pump timer.
+    pub timer: u8,
+}
+
+impl PumpState {
+    /// Pumps are numbered from 1 in the UI.
+    pub fn pump_address(pump_id: u8) -> Option<u8> {
+        let address = FIRST_PUMP_ADDRESS.checked_add(pump_id.checked_sub(1)?)?;
+        Self::is_pump_address(address).then_some(address)
+    }
+
+    pub fn is_pump_address(address: u8) -> bool {
+        (FIRST_PUMP_ADDRESS..=LAST_PUMP_ADDRESS).contains(&address)
+    }
+
+    pub fn from_packet(packet: &[u8]) -> Result<PumpState, PoolError> {
+        if packet.len() < PAYLOAD_IDX + STATUS_PAYLOAD_LEN {
+            return Err(PoolError::PayloadTooShort("Pump status"));
+        }
+        Ok(PumpState {
+            address: packet[SRC_IDX],
+            running: packet[RUN_IDX] == RUNNING,
+            mode: packet[MODE_IDX],
+            drive_state: packet[DRIVE_STATE_IDX],
+            watts: u16::from_be_bytes([packet[WATTS_IDX], packet[WATTS_IDX + 1]]),
+            rpm: u16::from_be_bytes([packet[RPM_IDX], packet[RPM_IDX + 1]]),
+            gpm: packet[GPM_IDX],
+            percent: packet[PERCENT_IDX],
+            error: packet[ERROR_IDX],
+            timer: packet[TIMER_IDX],
+        })
+    }
+
+    /// Builds the status payload as the pump sends it, used by the simulator.
+    pub fn encode_payload(&self, hour: u8, minute: u8) -> Vec<u8> {
+        let mut payload = vec![0; STATUS_PAYLOAD_LEN];
+        let mut set = |idx: usize, value: u8| payload[idx - PAYLOAD_IDX] = value;
+        set(RUN_IDX, if self.running { RUNNING } else { STOPPED });
+        set(MODE_IDX, self.mode);
+        set(DRIVE_STATE_IDX, self.drive_state);
+        let watts = self.watts.to_be_bytes();
+        set(WATTS_IDX, watts[0]);
+        set(WATTS_IDX + 1, watts[1]);
+        let rpm = self.rpm.to_be_bytes();
+        set(RPM_IDX, rpm[0]);
+        set(RPM_IDX + 1, rpm[1]);
+        set(GPM_IDX, self.gpm);
+        set(PERCENT_IDX, self.percent);
+        set(ERROR_IDX, self.error);
+        set(TIMER_IDX, self.timer);
+        set(HOUR_IDX, hour);
+        set(MINUTE_IDX, minute);
+        payload
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_pump_state_from_packet() {
+    // Pump 1 running at 2450 RPM, 1050 W, 42 GPM.
+    let packet = vec![
+        0x00, 0x10, 0x60, 0x07, 0x0F, 0x0A, 0x00, 0x02, 0x04, 0x1A, 0x09, 0x92, 0x2A, 0x00, 0x00,
+        0x00, 0x00, 0x05, 0x0E, 0x21,
+    ];
+    let state = PumpState::from_packet(&packet).unwrap();
+    assert_eq!(
+        state,
+        PumpState {
+            address: 0x60,
+            running: true,
+            mode: 0,
+            drive_state: 2,
+            watts: 1050,
+            rpm: 2450,
+            gpm: 42,
+            percent: 0,
+            error: 0,
+            timer: 5,
+        }
+    );
+    assert_eq!(state.encode_payload(0x0E, 0x21), packet[5..].to_vec());
+
+    assert!(PumpState::from_packet(&packet[..12]).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_pump_command_encode() {
+    let frames = PumpCommand::Rpm { value: 2500 }.encode(0x60, 0x21).unwrap();
+    let actions: Vec<u8> = frames.iter().map(|(_, action)| *action).collect();
+    assert_eq!(
+        actions,
+        vec![REMOTE_CONTROL_ACTION, SET_SPEED_ACTION, RUN_ACTION]
+    );
+    assert_eq!(
+        frames[1].0,
+        vec![
+            0xFF, 0x00, 0xFF, 0xA5, 0x00, 0x60, 0x21, 0x01, 0x04, 0x02, 0xC4, 0x09, 0xC4, 0x02,
+            0xBE
+        ]
+    );
+    assert_eq!(
+        PumpCommand::Release.encode(0x61, 0x21).unwrap()[0].0[3..9],
+        [0xA5, 0x00, 0x61, 0x21, 0x04, 0x01]
+    );
+
+    assert!(!PumpCommand::Program { value: 5 }.is_valid());
+    assert!(!PumpCommand::Rpm { value: 100 }.is_valid());
+    assert_eq!(PumpState::pump_address(1), Some(0x60));
+    assert_eq!(PumpState::pump_address(16), Some(0x6F));
+    assert_eq!(PumpState::pump_address(0), None);
+    assert_eq!(PumpState::pump_address(17), None);
+}
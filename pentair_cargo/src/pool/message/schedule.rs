@@ -0,0 +1,170 @@
+// Schedules and egg timers of the panel: 0xD1 asks for one, 0x11 is the answer and 0x91
+// sets it. The payload is id, circuit, start hour, start minute, end hour, end minute, days.
+
+use chrono::{NaiveTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+pub const SCHEDULE_ACTION: u8 = 0x11;
+pub const SET_SCHEDULE_ACTION: u8 = 0x91;
+pub const SCHEDULE_REQUEST: u8 = 0xD1;
+
+/// Schedule slots of an EasyTouch.
+pub const MAX_SCHEDULES: u8 = 12;
+
+/// The start hour of an egg timer, its "end" is the duration.
+const EGG_TIMER_HOUR: u8 = 25;
+const SCHEDULE_PAYLOAD_LEN: usize = 7;
+/// Egg timers run for less than a day.
+const MAX_EGG_TIMER_MINUTES: u16 = 24 * 60 - 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleTiming {
+    /// Runs the circuit between the times on the days.
+    Schedule {
+        start: NaiveTime,
+        end: NaiveTime,
+        days: Vec<Weekday>,
+    },
+    /// Turns the circuit off this long after it was turned on by hand.
+    EggTimer { minutes: u16 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u8,
+    pub circuit: u8,
+    #[serde(flatten)]
+    pub timing: ScheduleTiming,
+}
+
+// Bit 0 of the days is Sunday, as in the clock.
+fn days_mask(days: &[Weekday]) -> u8 {
+    days.iter()
+        .fold(0, |mask, day| mask | 1 << day.num_days_from_sunday())
+}
+
+fn days_from_mask(mask: u8) -> Vec<Weekday> {
+    [
+        Weekday::Sun,
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+    ]
+    .into_iter()
+    .filter(|day| mask & 1 << day.num_days_from_sunday() != 0)
+    .collect()
+}
+
+impl Schedule {
+    /// Decodes the payload of 0x11. Returns the slot id and None for an empty slot.
+    pub fn from_payload(payload: &[u8]) -> Option<(u8, Option<Schedule>)> {
+        let [id, circuit, start_hour, start_minute, end_hour, end_minute, days, ..] = *payload
+        else {
+            return None;
+        };
+        if circuit == 0 {
+            return Some((id, None));
+        }
+        let timing = if start_hour == EGG_TIMER_HOUR {
+            ScheduleTiming::EggTimer {
+                minutes: end_hour as u16 * 60 + end_minute as u16,
+            }
+        } else {
+            ScheduleTiming::Schedule {
+                start: NaiveTime::from_hms_opt(start_hour as u32, start_minute as u32, 0)?,
+                end: NaiveTime::from_hms_opt(end_hour as u32, end_minute as u32, 0)?,
+                days: days_from_mask(days),
+            }
+        };
+        Some((
+            id,
+            Some(Schedule {
+                id,
+                circuit,
+                timing,
+            }),
+        ))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let timing_valid = match &self.timing {
+            ScheduleTiming::Schedule { days, .. } => !days.is_empty(),
+            ScheduleTiming::EggTimer { minutes } => (1..=MAX_EGG_TIMER_MINUTES).contains(minutes),
+        };
+        (1..=MAX_SCHEDULES).contains(&self.id) && self.circuit != 0 && timing_valid
+    }
+
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let times = match &self.timing {
+            ScheduleTiming::Schedule { start, end, days } => [
+                start.hour() as u8,
+                start.minute() as u8,
+                end.hour() as u8,
+                end.minute() as u8,
+                days_mask(days),
+            ],
+            ScheduleTiming::EggTimer { minutes } => [
+                EGG_TIMER_HOUR,
+                0,
+                (minutes / 60) as u8,
+                (minutes % 60) as u8,
+                0,
+            ],
+        };
+        let mut payload = vec![self.id, self.circuit];
+        payload.extend_from_slice(&times);
+        payload
+    }
+
+    /// The payload that clears a schedule slot.
+    pub fn encode_empty_payload(id: u8) -> Vec<u8> {
+        let mut payload = vec![0; SCHEDULE_PAYLOAD_LEN];
+        payload[0] = id;
+        payload
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_schedule_payload() {
+    // Pool from 8:00 to 17:30 on weekdays.
+    let payload = [0x01, 0x06, 0x08, 0x00, 0x11, 0x1E, 0x3E];
+    let (id, schedule) = Schedule::from_payload(&payload).unwrap();
+    let schedule = schedule.unwrap();
+    assert_eq!(id, 1);
+    assert_eq!(
+        schedule.timing,
+        ScheduleTiming::Schedule {
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(17, 30, 0).unwrap(),
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri
+            ],
+        }
+    );
+    assert_eq!(schedule.encode_payload(), payload.to_vec());
+
+    // Spa egg timer of 2:15.
+    let payload = [0x02, 0x01, 0x19, 0x00, 0x02, 0x0F, 0x00];
+    let schedule = Schedule::from_payload(&payload).unwrap().1.unwrap();
+    assert_eq!(schedule.timing, ScheduleTiming::EggTimer { minutes: 135 });
+    assert_eq!(schedule.encode_payload(), payload.to_vec());
+
+    assert_eq!(
+        Schedule::from_payload(&Schedule::encode_empty_payload(3)),
+        Some((3, None))
+    );
+    assert!(Schedule::from_payload(&[0x01, 0x06, 0x08]).is_none());
+    let json = r#"{"id":4,"circuit":6,"type":"schedule","start":"09:00","end":"10:00","days":["Sat","Sun"]}"#;
+    let schedule: Schedule = serde_json::from_str(json).unwrap();
+    assert!(schedule.is_valid());
+    assert_eq!(schedule.encode_payload()[6], 0x41);
+}
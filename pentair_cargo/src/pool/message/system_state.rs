@@ -1,93 +1,330 @@
-use log::{debug, error, trace, warn};
+use crate::error::PoolError;
+use crate::pool::message::circuit::{normalize_name, Circuit};
+use crate::pool::message::heat::HeatSettings;
+use crate::pool::message::PAYLOAD_IDX;
+use log::debug;
 use serde::Serialize;
-use serial::{self, Error};
+use std::collections::BTreeMap;
 
+/// Units the panel reports the temperatures in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum TemperatureUnit {
+    Fahrenheit,
+    Celsius,
+}
 
-/// The decoded package with the system state.
+impl TemperatureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Fahrenheit => "°F",
+            TemperatureUnit::Celsius => "°C",
+        }
+    }
+}
+
+const HOUR_IDX: usize = PAYLOAD_IDX;
+const MINUTE_IDX: usize = 6;
+// Circuits 1-8 are in the first mask byte, 9-16 in the next one and so on.
+const MASK_IDX: usize = 7;
+const MASK_BYTES: usize = 5;
+const UNITS_IDX: usize = 14;
+const HEAT_STATUS_IDX: usize = 15;
+const WATER_TEMP_IDX: usize = 19;
+const SPA_TEMP_IDX: usize = 20;
+const AIR_TEMP_IDX: usize = 23;
+const SOLAR_TEMP_IDX: usize = 24;
+/// Length of the status payload sent by EasyTouch.
+const STATUS_PAYLOAD_LEN: usize = 29;
+
+const CELSIUS_MASK: u8 = 0x04;
+const HEATER_MASK: u8 = 0x0C; // Pool (0x04) or spa (0x08) heater.
+const SOLAR_MASK: u8 = 0x30; // Pool (0x10) or spa (0x20) solar.
+const POOL_HEATER_MASK: u8 = 0x04;
+const POOL_SOLAR_MASK: u8 = 0x10;
+
+/// Where a panel family keeps the values of its status broadcast (0x02), the offsets are
+/// in the packet without the header.
+pub struct StatusLayout {
+    /// Circuits from 1 on, 8 in a byte.
+    pub mask_idx: usize,
+    pub mask_bytes: usize,
+    /// Features that have a mask of their own: the offset, the bytes and the first number.
+    pub feature_mask: Option<(usize, usize, u8)>,
+    pub units_idx: Option<usize>,
+    pub heat_status_idx: Option<usize>,
+    pub water_temp_idx: usize,
+    pub air_temp_idx: usize,
+    pub solar_temp_idx: usize,
+    /// The circuits of a panel with the default configuration.
+    pub default_circuits: fn() -> Vec<Circuit>,
+    /// A circuit we know nothing about but its number.
+    pub new_circuit: fn(u8) -> Circuit,
+}
+
+/// Status of EasyTouch and IntelliTouch.
+pub const EASY_TOUCH_LAYOUT: StatusLayout = StatusLayout {
+    mask_idx: MASK_IDX,
+    mask_bytes: MASK_BYTES,
+    feature_mask: None,
+    units_idx: Some(UNITS_IDX),
+    heat_status_idx: Some(HEAT_STATUS_IDX),
+    water_temp_idx: WATER_TEMP_IDX,
+    air_temp_idx: AIR_TEMP_IDX,
+    solar_temp_idx: SOLAR_TEMP_IDX,
+    default_circuits: Circuit::defaults,
+    new_circuit: Circuit::new,
+};
 
+/// The decoded package with the system state.
 #[derive(Clone, Debug)]
 pub struct SystemState {
-
-    // Different switches, usually in the state on/off
-    pool_on: bool,
-    spa_on: bool,
-    aux_circuits: Vec<bool>,
-    feature_circuits: Vec<bool>,
+    // Circuits by their number.
+    circuits: BTreeMap<u8, Circuit>,
 
     // Block of temperatures
     water_temp: u32,
     air_temp: u32,
     solar_temp: u32,
+    temperature_unit: TemperatureUnit,
+
+    // Heat sources that are currently running.
+    heater_on: bool,
+    solar_on: bool,
+
+    // Setpoints and modes, they come in a separate heat status packet.
+    heat: Option<HeatSettings>,
 }
 
+impl Default for SystemState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SystemState {
     pub fn new() -> SystemState {
+        Self::with_circuits(Circuit::defaults())
+    }
+
+    pub fn with_circuits(circuits: Vec<Circuit>) -> SystemState {
         SystemState {
-            pool_on: false,
-            spa_on: false,
-            aux_circuits: Vec::new(),
-            feature_circuits: Vec::new(),
+            circuits: circuits
+                .into_iter()
+                .map(|circuit| (circuit.number, circuit))
+                .collect(),
             water_temp: 0,
             air_temp: 0,
             solar_temp: 0,
+            temperature_unit: TemperatureUnit::Fahrenheit,
+            heater_on: false,
+            solar_on: false,
+            heat: None,
         }
     }
-    pub fn from_packet(packet: &[u8]) -> Result<SystemState, serial::Error> {
+    pub fn from_packet(packet: &[u8]) -> Result<SystemState, PoolError> {
+        Self::from_status(packet, &EASY_TOUCH_LAYOUT)
+    }
+
+    /// Decodes the status broadcast of a panel with the given layout.
+    pub fn from_status(packet: &[u8], layout: &StatusLayout) -> Result<SystemState, PoolError> {
         debug!("Processing packet {:?}", packet);
-        if packet.len() < 8 {
-            return Err(Error::new(
-                serial::ErrorKind::InvalidInput,
-                "Packet too short",
-            ));
+
+        let masks_end = layout
+            .feature_mask
+            .map_or(0, |(idx, bytes, _)| idx + bytes)
+            .max(layout.mask_idx + layout.mask_bytes);
+        let last_idx = [
+            layout.water_temp_idx,
+            layout.air_temp_idx,
+            layout.solar_temp_idx,
+            layout.units_idx.unwrap_or_default(),
+            layout.heat_status_idx.unwrap_or_default(),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or_default();
+        if packet.len() <= last_idx || packet.len() < masks_end {
+            return Err(PoolError::PayloadTooShort("Status"));
         }
 
-        let mut state = Self::new();
-
-        const MASK_IDX: usize = 7;
-        const SPA_MASK: u8 = 0x01;
-        const AUX1_MASK: u8 = 0x02;
-        const AUX2_MASK: u8 = 0x04;
-        const AUX3_MASK: u8 = 0x08;
-        const POOL_MASK: u8 = 0x20;
-        const FEATURE1_MASK: u8 = 0x10;
-        const FEATURE2_MASK: u8 = 0x40;
-        const FEATURE3_MASK: u8 = 0x80;
-
-        {
-            state.pool_on = (packet[MASK_IDX] & POOL_MASK) != 0;
-            state.spa_on = (packet[MASK_IDX] & SPA_MASK) != 0;
-            state.aux_circuits.push((packet[MASK_IDX] & AUX1_MASK) != 0);
-            state.aux_circuits.push((packet[MASK_IDX] & AUX2_MASK) != 0);
-            state.aux_circuits.push((packet[MASK_IDX] & AUX3_MASK) != 0);
-            state
-                .feature_circuits
-                .push((packet[MASK_IDX] & FEATURE1_MASK) != 0);
-            state
-                .feature_circuits
-                .push((packet[MASK_IDX] & FEATURE2_MASK) != 0);
-            state
-                .feature_circuits
-                .push((packet[MASK_IDX] & FEATURE3_MASK) != 0);
+        let mut state = Self::with_circuits((layout.default_circuits)());
+
+        let mut masks = vec![(layout.mask_idx, layout.mask_bytes, 1)];
+        masks.extend(layout.feature_mask);
+        for (idx, bytes, first) in masks {
+            for (byte_idx, mask) in packet[idx..idx + bytes].iter().enumerate() {
+                for bit in 0..8 {
+                    let number = first + (byte_idx * 8 + bit) as u8;
+                    let on = mask & (1 << bit) != 0;
+                    // Circuits we don't know about are only added when they are on.
+                    if on || state.circuits.contains_key(&number) {
+                        state
+                            .circuits
+                            .entry(number)
+                            .or_insert_with(|| (layout.new_circuit)(number))
+                            .on = on;
+                    }
+                }
+            }
         }
 
+        if let Some(units_idx) = layout.units_idx {
+            state.temperature_unit = if (packet[units_idx] & CELSIUS_MASK) != 0 {
+                TemperatureUnit::Celsius
+            } else {
+                TemperatureUnit::Fahrenheit
+            };
+        }
+        if let Some(heat_status_idx) = layout.heat_status_idx {
+            state.heater_on = (packet[heat_status_idx] & HEATER_MASK) != 0;
+            state.solar_on = (packet[heat_status_idx] & SOLAR_MASK) != 0;
+        }
+        state.water_temp = packet[layout.water_temp_idx] as u32;
+        state.air_temp = packet[layout.air_temp_idx] as u32;
+        state.solar_temp = packet[layout.solar_temp_idx] as u32;
+
         Ok(state)
     }
 
-    // Checks the current state
+    /// Takes the values from a new status broadcast, keeps what the status does not carry:
+    /// the heat settings and the circuit definitions.
+    pub fn update_status(&mut self, status: SystemState) {
+        let mut circuits = std::mem::take(&mut self.circuits);
+        for circuit in circuits.values_mut() {
+            circuit.on = status.circuits.get(&circuit.number).is_some_and(|c| c.on);
+        }
+        for (number, circuit) in status.circuits {
+            circuits.entry(number).or_insert(circuit);
+        }
+        let heat = self.heat.take();
+        *self = SystemState {
+            circuits,
+            heat,
+            ..status
+        };
+    }
+
+    // Checks the current state, by the control ids.
     pub fn get_controls_state(&self) -> Vec<(String, bool)> {
+        self.circuits
+            .values()
+            .map(|circuit| (circuit.id.clone(), circuit.on))
+            .collect()
+    }
+
+    pub fn get_circuits(&self) -> Vec<Circuit> {
+        self.circuits.values().cloned().collect()
+    }
+
+    /// The circuit with this number, added with the default name if it is not known yet.
+    pub fn define_circuit(&mut self, number: u8) -> &mut Circuit {
+        self.circuits
+            .entry(number)
+            .or_insert_with(|| Circuit::new(number))
+    }
+
+    pub fn remove_circuit(&mut self, number: u8) {
+        self.circuits.remove(&number);
+    }
+
+    pub fn circuits_mut(&mut self) -> impl Iterator<Item = &mut Circuit> {
+        self.circuits.values_mut()
+    }
+
+    /// Builds the status payload (0x02) as the panel sends it, used by the simulator.
+    pub fn encode_payload(&self, hour: u8, minute: u8) -> Vec<u8> {
+        let mut payload = vec![0; STATUS_PAYLOAD_LEN];
+        let mut set = |idx: usize, value: u8| payload[idx - PAYLOAD_IDX] = value;
+        set(HOUR_IDX, hour);
+        set(MINUTE_IDX, minute);
+
+        let mut masks = [0u8; MASK_BYTES];
+        for circuit in self.circuits.values().filter(|circuit| circuit.on) {
+            let bit = circuit.number as usize - 1;
+            if let Some(mask) = masks.get_mut(bit / 8) {
+                *mask |= 1 << (bit % 8);
+            }
+        }
+        for (byte_idx, mask) in masks.iter().enumerate() {
+            set(MASK_IDX + byte_idx, *mask);
+        }
+
+        if self.temperature_unit == TemperatureUnit::Celsius {
+            set(UNITS_IDX, CELSIUS_MASK);
+        }
+        let mut heat_status = 0;
+        if self.heater_on {
+            heat_status |= POOL_HEATER_MASK;
+        }
+        if self.solar_on {
+            heat_status |= POOL_SOLAR_MASK;
+        }
+        set(HEAT_STATUS_IDX, heat_status);
+        set(WATER_TEMP_IDX, self.water_temp as u8);
+        set(SPA_TEMP_IDX, self.water_temp as u8);
+        set(AIR_TEMP_IDX, self.air_temp as u8);
+        set(SOLAR_TEMP_IDX, self.solar_temp as u8);
+        payload
+    }
+
+    /// Turns a circuit on or off by its number, returns false for unknown circuits.
+    pub fn set_circuit(&mut self, circuit: u8, on: bool) -> bool {
+        match self.circuits.get_mut(&circuit) {
+            Some(circuit) => {
+                circuit.on = on;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_temperatures(&mut self, water: u32, air: u32, solar: u32) {
+        self.water_temp = water;
+        self.air_temp = air;
+        self.solar_temp = solar;
+    }
+
+    // Maps a control to the circuit number used in the set circuit command. The control is
+    // the id of the circuit or its name, the name is matched ignoring case and spaces.
+    pub fn circuit_number(&self, control_name: &str) -> Option<u8> {
+        let name = normalize_name(control_name);
+        self.circuits
+            .values()
+            .find(|circuit| circuit.id == control_name)
+            .or_else(|| {
+                self.circuits
+                    .values()
+                    .find(|circuit| normalize_name(&circuit.name) == name)
+            })
+            .map(|circuit| circuit.number)
+    }
+
+    // Temperatures in the units reported by the panel, see get_temperature_unit.
+    pub fn get_temperatures(&self) -> Vec<(String, f32)> {
         vec![
-            ("pool".to_string(), self.pool_on),
-            ("spa".to_string(), self.spa_on),
+            ("water".to_string(), self.water_temp as f32),
+            ("air".to_string(), self.air_temp as f32),
+            ("solar".to_string(), self.solar_temp as f32),
         ]
     }
 
-    //
-    pub fn get_temperatures(&self) -> Vec<(String, f32)> {
+    pub fn get_temperature_unit(&self) -> TemperatureUnit {
+        self.temperature_unit
+    }
+
+    pub fn get_heat_settings(&self) -> Option<HeatSettings> {
+        self.heat.clone()
+    }
+
+    pub fn set_heat_settings(&mut self, heat: Option<HeatSettings>) {
+        self.heat = heat;
+    }
+
+    // Heat sources and whether they are running now.
+    pub fn get_heating_state(&self) -> Vec<(String, bool)> {
         vec![
-            ("water".to_string(), 82.),
-            ("air".to_string(), 72.),
-            ("solar".to_string(), 83.),
+            ("heater".to_string(), self.heater_on),
+            ("solar".to_string(), self.solar_on),
         ]
     }
 }
@@ -95,7 +332,97 @@ impl SystemState {
 #[cfg(test)]
 #[test]
 fn test_system_state_from_packet() {
-    let packet = vec![
+    use crate::pool::message::{PacketType, ProtocolPacket};
+
+    struct Expected {
+        // Circuits that are on.
+        on: Vec<u8>,
+        water: u32,
+        air: u32,
+        solar: u32,
+        unit: TemperatureUnit,
+        heater_on: bool,
+        solar_on: bool,
+    }
+
+    // Status broadcasts (0x02) laid out as EasyTouch sends them, header and checksum
+    // stripped. They are built from the offsets above, not captured from a panel.
+    let samples = vec![
+        (
+            // Pool running in the afternoon, nothing heating.
+            vec![
+                0x01, 0x0F, 0x10, 0x02, 0x1D, 0x0D, 0x1D, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x52, 0x52, 0x00, 0x00, 0x48, 0x53, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
+            Expected {
+                on: vec![6],
+                water: 82,
+                air: 72,
+                solar: 83,
+                unit: TemperatureUnit::Fahrenheit,
+                heater_on: false,
+                solar_on: false,
+            },
+        ),
+        (
+            // Spa with the gas heater on, the panel is set to Celsius.
+            vec![
+                0x01, 0x0F, 0x10, 0x02, 0x1D, 0x13, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x04, 0x08, 0x00, 0x00, 0x00, 0x1C, 0x26, 0x00, 0x00, 0x16, 0x1E, 0x00, 0x00, 0x03,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
+            Expected {
+                on: vec![1],
+                water: 28,
+                air: 22,
+                solar: 30,
+                unit: TemperatureUnit::Celsius,
+                heater_on: true,
+                solar_on: false,
+            },
+        ),
+        (
+            // Pool with aux 1 and aux 3, heated by solar.
+            vec![
+                0x01, 0x0F, 0x10, 0x02, 0x1D, 0x0B, 0x2A, 0x2A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x10, 0x00, 0x00, 0x00, 0x4F, 0x4F, 0x00, 0x00, 0x5A, 0x6E, 0x00, 0x00, 0x01,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ],
+            Expected {
+                on: vec![2, 4, 6],
+                water: 79,
+                air: 90,
+                solar: 110,
+                unit: TemperatureUnit::Fahrenheit,
+                heater_on: false,
+                solar_on: true,
+            },
+        ),
+    ];
+
+    for (packet, expected) in samples {
+        let state = SystemState::from_packet(&packet).unwrap();
+        let on: Vec<u8> = state
+            .get_circuits()
+            .iter()
+            .filter(|circuit| circuit.on)
+            .map(|circuit| circuit.number)
+            .collect();
+        assert_eq!(on, expected.on);
+        assert_eq!(state.get_circuits().len(), 8);
+        assert_eq!(state.water_temp, expected.water);
+        assert_eq!(state.air_temp, expected.air);
+        assert_eq!(state.solar_temp, expected.solar);
+        assert_eq!(state.get_temperature_unit(), expected.unit);
+        assert_eq!(state.heater_on, expected.heater_on);
+        assert_eq!(state.solar_on, expected.solar_on);
+    }
+
+    // The packet this test started with is not a status broadcast: the header is zeros, the
+    // values are 32 bit little endian words and the circuit mask at 7 is empty, so pool, aux 1
+    // and aux 3 were never on in it. The board does not decode it as a status.
+    let original = [
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x00, 0x00,
         0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x53, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
@@ -104,16 +431,46 @@ fn test_system_state_from_packet() {
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf5, 0x01, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00,
     ];
+    let decoded = crate::pool::board::BoardKind::EasyTouch
+        .board()
+        .decode_packet(&original);
+    assert!(!matches!(
+        decoded,
+        Ok(ProtocolPacket {
+            decoded: PacketType::Status(_),
+            ..
+        })
+    ));
+
+    // The encoder produces what the decoder reads.
+    let mut state = SystemState::new();
+    state.set_circuit(6, true);
+    state.set_circuit(3, true);
+    state.set_temperatures(80, 70, 90);
+    state.heater_on = true;
+    let mut packet = vec![0x01, 0x0F, 0x10, 0x02, STATUS_PAYLOAD_LEN as u8];
+    packet.extend(state.encode_payload(12, 30));
+    let decoded = SystemState::from_packet(&packet).unwrap();
+    assert_eq!(decoded.get_circuits(), state.get_circuits());
+    assert_eq!(decoded.get_temperatures(), state.get_temperatures());
+    assert_eq!(decoded.get_heating_state(), state.get_heating_state());
+
+    // A circuit beyond the defaults shows up when it is on.
+    packet[MASK_IDX + 1] = 0x04;
+    let mut decoded = SystemState::from_packet(&packet).unwrap();
+    assert_eq!(decoded.circuit_number("circuit11"), Some(11));
+    assert!(decoded
+        .get_circuits()
+        .iter()
+        .any(|c| c.number == 11 && c.on));
+    // And stays in the table, turned off, when the next status does not have it.
+    packet[MASK_IDX + 1] = 0;
+    decoded.update_status(SystemState::from_packet(&packet).unwrap());
+    assert!(decoded
+        .get_circuits()
+        .iter()
+        .any(|c| c.number == 11 && !c.on));
 
-    // \\x00\\xf6\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x02\\x00\\x02\\x00\\xf7\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x06\\x01\\n\\x00\\xf8\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xf9\\x01\\x00\\x00\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xfa\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xfb\\x01\\x00\\x00\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xfc\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xfe\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xff\\x01\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\x00\\xff\\x02\\x00\\x00\\x1f\\x03\\x00\\x00\\xff\\xff\\xff\\xff\\x00\\x00\\x00\\x00\\x06\\x00\\x00\\x00\\x07\\x00\\x00\\x00\\x00\\x00\\x00\\x00'"
-
-    let state = SystemState::from_packet(&packet).unwrap();
-    assert!(state.pool_on);
-    assert!(!state.spa_on);
-    assert!(state.aux_circuits[0]);
-    assert!(!state.aux_circuits[1]);
-    assert!(state.aux_circuits[2]);
-    assert!(!state.feature_circuits[0]);
-    assert!(!state.feature_circuits[1]);
-    assert!(!state.feature_circuits[2]);
+    // Truncated status is rejected instead of reading past the end.
+    assert!(SystemState::from_packet(&[0x01, 0x0F, 0x10, 0x02, 0x03, 0x0D, 0x1D, 0x20]).is_err());
 }
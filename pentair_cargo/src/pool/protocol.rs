@@ -1,10 +1,34 @@
+use crate::error::PoolError;
+use crate::pool::board::{self, Board, BoardKind};
+use crate::pool::command::{CommandHandle, CommandId, CommandPolicy, CommandState, CommandTracker};
+use crate::pool::frame::DecoderCounters;
 use crate::pool::message;
+use crate::pool::message::chlorinator::{self, ChlorinatorMessage, ChlorinatorState};
+use crate::pool::message::circuit::{normalize_name, CircuitFunction};
+use crate::pool::message::clock::{self, PanelClock};
+use crate::pool::message::equipment::{self, EquipmentConfig};
+use crate::pool::message::heat::{self, Body, HeatMode};
+use crate::pool::message::intellichem::ChemistryState;
+use crate::pool::message::light::{self, LightGroup, LightState, LightTheme};
+use crate::pool::message::panel_config::{self, PanelConfig};
+use crate::pool::message::pump_state::{PumpCommand, PumpState};
+use crate::pool::message::schedule::{self, Schedule};
 use crate::pool::message::system_state::SystemState;
 use chrono::{DateTime, Local};
-use log::{debug, error};
+use log::{debug, error, info, trace, warn};
 use serde::Serialize;
-use serial::{self, Error, SerialPort};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// A pump under remote control goes back to the panel if it does not hear from us.
+const PUMP_KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// The chlorinator stops generating if the master is silent for too long.
+const CHLORINATOR_INTERVAL: Duration = Duration::from_secs(5);
+/// The panel clock is set when it is off by more than this.
+const CLOCK_DRIFT_LIMIT: chrono::TimeDelta = chrono::TimeDelta::minutes(2);
+/// Don't set the clock again sooner, in case the panel does not take it.
+const CLOCK_SET_INTERVAL: Duration = Duration::from_secs(3600);
 
 #[derive(Clone, Serialize)]
 pub struct PacketLogElement {
@@ -12,16 +36,93 @@ pub struct PacketLogElement {
     pub timestamp: DateTime<Local>,
 }
 
+/// Result of writing a queued packet to the bus.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransmitResult {
+    /// The packet was written while the bus was idle and was not garbled.
+    Sent,
+    /// Gave up after too many collisions or a port error.
+    Failed,
+}
+
+/// A packet waiting for the bus to become available.
+pub struct OutgoingPacket {
+    /// The command this packet belongs to.
+    pub id: CommandId,
+    /// Complete frame ready to be written to the bus.
+    pub frame: Vec<u8>,
+    /// Number of attempts that ended with a collision.
+    pub collisions: u32,
+}
+
+/// Protocol errors seen on the bus since the start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ErrorCounters {
+    pub unrecognized_bytes: u32,
+    pub corrupted_packets: u32,
+    pub short_packets: u32,
+    pub unknown_protocol: u32,
+}
+
+fn rejected(reason: String) -> PoolError {
+    error!("{}", reason);
+    PoolError::CommandRejected(reason)
+}
+
 pub struct PoolProtocol {
     // This is the only one thread that reads/writes the port.
     // communication_thread: std::thread::JoinHandle,
     system_state: SystemState,
 
-    // The version of the system
-    version: u32,
+    // The panel family, EasyTouch unless set. With board_detection the traffic can show
+    // another one, the configuration is then requested again from it.
+    board: Box<dyn Board>,
+    board_detection: bool,
+    configuration_requested: bool,
+    // The controller id follows the board when the configuration does not set one.
+    board_controller_id: bool,
+
+    // Configuration learned from the panel.
+    panel_config: PanelConfig,
+    equipment: EquipmentConfig,
+
+    // IntelliChem status from its last answer to the panel.
+    chemistry: Option<ChemistryState>,
+
+    // IntelliBrite lights, the theme we sent waits for the acknowledgement of the panel.
+    lights: LightState,
+    pending_light_theme: Option<LightTheme>,
+
+    // Schedules of the panel by their slot id.
+    schedules: BTreeMap<u8, Schedule>,
+
+    // Labels from the configuration file, they replace the circuit names from the panel.
+    device_names: HashMap<String, String>,
+
+    // Last status of every pump we heard from, by the pump address.
+    pumps: BTreeMap<u8, PumpState>,
+
+    // Pumps we took over from the panel: the last command and when it was sent.
+    remote_pumps: BTreeMap<u8, (PumpCommand, Instant)>,
 
-    // We just sent a circuit change request and is waitng to CiercuitStatusREsponse
-    waiting_for_circuit_status_response: bool,
+    // None until we hear from or about a chlorinator.
+    chlorinator: Option<ChlorinatorState>,
+
+    // Last clock broadcast from the panel.
+    clock: Option<PanelClock>,
+
+    // Set the panel clock from the host time when it drifts, and when we last did it.
+    clock_sync: bool,
+    clock_set_at: Option<Instant>,
+
+    // When we are the chlorinator master: the output we ask for and when we last did.
+    chlorinator_output: Option<(u8, Option<Instant>)>,
+
+    // Our address on the bus, used as a source of the packets we send.
+    controller_id: u8,
+
+    // Commands we sent and are waiting to be acknowledged.
+    commands: CommandTracker,
 
     /// Keep a few recent packets for debugging/logging.
     recent_packets: Vec<PacketLogElement>,
@@ -32,22 +133,40 @@ pub struct PoolProtocol {
     short_packets: AtomicU32,
     unknown_protocol: AtomicU32,
 
-    /// A queue of outgoing packets.
-    outgoing: Vec<String>,
+    /// A queue of outgoing packets, written by the port thread when the bus is idle.
+    outgoing: VecDeque<OutgoingPacket>,
 }
 
 impl PoolProtocol {
-    pub fn new() -> PoolProtocol {
+    pub fn new(controller_id: u8, command_policy: CommandPolicy) -> PoolProtocol {
         PoolProtocol {
             system_state: SystemState::new(),
-            version: 0,
-            waiting_for_circuit_status_response: false,
+            board: BoardKind::EasyTouch.board(),
+            board_detection: false,
+            configuration_requested: false,
+            board_controller_id: false,
+            panel_config: PanelConfig::default(),
+            equipment: EquipmentConfig::default(),
+            chemistry: None,
+            lights: LightState::default(),
+            pending_light_theme: None,
+            schedules: BTreeMap::new(),
+            device_names: HashMap::new(),
+            pumps: BTreeMap::new(),
+            remote_pumps: BTreeMap::new(),
+            chlorinator: None,
+            clock: None,
+            clock_sync: false,
+            clock_set_at: None,
+            chlorinator_output: None,
+            controller_id,
+            commands: CommandTracker::new(command_policy),
             recent_packets: Vec::new(),
             unrecognized_bytes: AtomicU32::new(0),
             corrupted_packets: AtomicU32::new(0),
             short_packets: AtomicU32::new(0),
             unknown_protocol: AtomicU32::new(0),
-            outgoing: vec![],
+            outgoing: VecDeque::new(),
         }
     }
 
@@ -56,49 +175,679 @@ impl PoolProtocol {
         self.system_state.clone()
     }
 
+    /// Returns the last status of all the known pumps.
+    pub fn get_pumps(&self) -> Vec<PumpState> {
+        self.pumps.values().cloned().collect()
+    }
+
+    /// Returns what we learned about the panel configuration.
+    pub fn get_panel_config(&self) -> PanelConfig {
+        self.panel_config.clone()
+    }
+
+    /// Returns the IntelliChem readings if there is one on the bus.
+    pub fn get_chemistry(&self) -> Option<ChemistryState> {
+        self.chemistry.clone()
+    }
+
+    pub fn get_lights(&self) -> LightState {
+        self.lights.clone()
+    }
+
+    /// Returns the valves, solar and heaters as configured on the panel.
+    pub fn get_equipment_config(&self) -> EquipmentConfig {
+        self.equipment.clone()
+    }
+
+    pub fn get_schedules(&self) -> Vec<Schedule> {
+        self.schedules.values().cloned().collect()
+    }
+
+    /// Returns the time of the panel from its last broadcast.
+    pub fn get_clock(&self) -> Option<PanelClock> {
+        self.clock.clone()
+    }
+
+    /// Returns the chlorinator state if there is one on the bus.
+    pub fn get_chlorinator(&self) -> Option<ChlorinatorState> {
+        self.chlorinator.clone()
+    }
+
     pub fn get_recent_packets(&self) -> Vec<PacketLogElement> {
         self.recent_packets.clone()
     }
 
     pub fn process_packet(&mut self, packet: &[u8]) {
         debug!("Processing packet {:?}", packet);
-        match message::ProtocolPacket::decode_packet(packet) {
+        if self.board_detection {
+            if let Some(kind) = board::detect_board(packet) {
+                self.switch_board(kind);
+            }
+        }
+        match self.board.decode_packet(packet) {
             Ok(received_message) => {
                 self.log_packet(packet);
+                let source = received_message.get_source();
+                let destination = received_message.get_destination();
+                let action = received_message.get_action();
+                let argument = received_message.get_payload().first().copied();
                 match received_message.decoded {
                     message::PacketType::Status(status) => {
-                        self.system_state = status;
+                        self.system_state.update_status(status);
+                        self.apply_circuit_config();
+                    }
+                    message::PacketType::CircuitDefinition(definition) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.panel_config
+                            .circuits
+                            .insert(definition.number, definition);
+                        self.apply_circuit_config();
+                    }
+                    message::PacketType::CustomName { index, name } => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.panel_config.custom_names.insert(index, name);
+                        self.apply_circuit_config();
+                    }
+                    message::PacketType::Schedule { id, schedule } => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        match schedule {
+                            Some(schedule) => self.schedules.insert(id, schedule),
+                            None => self.schedules.remove(&id),
+                        };
+                    }
+                    message::PacketType::ValveConfig(config) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.equipment.valves = Some(config);
+                    }
+                    message::PacketType::SolarConfig(config) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.equipment.set_solar(config);
+                    }
+                    // Somebody else changes the equipment, read back what the panel took.
+                    message::PacketType::SetValveConfig(_)
+                        if destination == message::PANEL_ADDRESS
+                            && source != self.controller_id =>
+                    {
+                        self.request_from_panel(
+                            equipment::VALVE_CONFIG_REQUEST,
+                            equipment::VALVE_CONFIG_ACTION,
+                            0,
+                        );
+                    }
+                    message::PacketType::SetSolarConfig(_)
+                        if destination == message::PANEL_ADDRESS
+                            && source != self.controller_id =>
+                    {
+                        self.request_from_panel(
+                            equipment::SOLAR_CONFIG_REQUEST,
+                            equipment::SOLAR_CONFIG_ACTION,
+                            0,
+                        );
+                    }
+                    message::PacketType::LightGroup(group) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.lights.group = Some(group);
+                    }
+                    message::PacketType::LightCommand(theme)
+                        if destination == message::PANEL_ADDRESS
+                            && source != self.controller_id =>
+                    {
+                        self.lights.theme = Some(theme);
                     }
-                    message::PacketType::CircuitStatusResponse => {
-                        self.waiting_for_circuit_status_response = false;
+                    message::PacketType::SetLightGroup(_)
+                        if destination == message::PANEL_ADDRESS
+                            && source != self.controller_id =>
+                    {
+                        self.request_from_panel(
+                            light::LIGHT_GROUP_REQUEST,
+                            light::LIGHT_GROUP_ACTION,
+                            0,
+                        );
+                    }
+                    message::PacketType::RemoteLayoutResponse(payload) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.panel_config.set_remote_layout(&payload);
+                    }
+                    message::PacketType::HeatStatus(settings) => {
+                        self.acknowledge_response(source, destination, action, argument);
+                        self.system_state.set_heat_settings(Some(settings));
+                    }
+                    message::PacketType::PumpStatus(pump) => {
+                        self.pumps.insert(pump.address, pump);
+                    }
+                    message::PacketType::ChemStatus(chemistry) => {
+                        self.chemistry = Some(chemistry);
+                    }
+                    message::PacketType::ClockBroadcast(panel_clock) => {
+                        self.check_clock_drift(&panel_clock, Local::now());
+                        self.clock = Some(panel_clock);
+                    }
+                    message::PacketType::CircuitStatusResponse(action)
+                        if received_message.get_destination() == self.controller_id =>
+                    {
+                        trace!("Action {:#04x} acknowledged", action);
+                        self.command_acknowledged(source, action, None);
+                        if action == light::LIGHT_COMMAND_ACTION {
+                            if let Some(theme) = self.pending_light_theme.take() {
+                                self.lights.theme = Some(theme);
+                            }
+                        }
+                    }
+                    message::PacketType::PumpReply(action)
+                        if received_message.get_destination() == self.controller_id =>
+                    {
+                        trace!(
+                            "Pump {:#04x} confirmed {:#04x}",
+                            received_message.get_source(),
+                            action
+                        );
+                        self.command_acknowledged(source, action, None);
                     }
                     message::PacketType::Unknown => {
-                        self.unrecognized_bytes.fetch_add(1, Ordering::Relaxed);
+                        self.count_error(&PoolError::UnsupportedAction(action));
                     }
                     _ => {}
                 }
             }
             Err(e) => {
-                error!("Error decoding packet: {:?}", e);
-                self.corrupted_packets.fetch_add(1, Ordering::Relaxed);
+                error!("Error decoding packet: {}", e);
+                self.count_error(&e);
+            }
+        }
+    }
+
+    /// Handles a packet in the IntelliChlor framing.
+    pub fn process_chlorinator_packet(&mut self, packet: &[u8]) {
+        debug!("Processing chlorinator packet {:?}", packet);
+        match ChlorinatorMessage::decode(packet) {
+            Ok(message) => {
+                self.log_packet(packet);
+                let mut state = self.chlorinator.clone().unwrap_or_default();
+                if matches!(message, ChlorinatorMessage::Status { .. })
+                    && ChlorinatorMessage::get_destination(packet) == chlorinator::MASTER_ADDRESS
+                {
+                    self.command_acknowledged(
+                        chlorinator::CHLORINATOR_ADDRESS,
+                        chlorinator::STATUS_ACTION,
+                        None,
+                    );
+                    // We don't read back our own set output commands.
+                    if let Some((output, _)) = self.chlorinator_output {
+                        state.update(&ChlorinatorMessage::SetOutput(output));
+                    }
+                }
+                if state.update(&message) {
+                    self.chlorinator = Some(state);
+                }
+            }
+            Err(e) => {
+                error!("Error decoding chlorinator packet: {}", e);
+                self.count_error(&e);
+            }
+        }
+    }
+
+    /// Counts a decoding error by its kind, the errors of the connection and the commands
+    /// are not counted.
+    pub fn count_error(&self, error: &PoolError) {
+        let counter = match error {
+            PoolError::Framing(_) | PoolError::Checksum | PoolError::InvalidPayload(_) => {
+                &self.corrupted_packets
             }
+            PoolError::PayloadTooShort(_) => &self.short_packets,
+            PoolError::UnsupportedProtocol(_) | PoolError::UnsupportedAction(_) => {
+                &self.unknown_protocol
+            }
+            PoolError::Io(_)
+            | PoolError::Timeout
+            | PoolError::PayloadTooLong(_)
+            | PoolError::CommandRejected(_) => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_error_counters(&self) -> ErrorCounters {
+        ErrorCounters {
+            unrecognized_bytes: self.unrecognized_bytes.load(Ordering::Relaxed),
+            corrupted_packets: self.corrupted_packets.load(Ordering::Relaxed),
+            short_packets: self.short_packets.load(Ordering::Relaxed),
+            unknown_protocol: self.unknown_protocol.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Adds what the frame decoder threw away to the error counters.
+    pub fn count_discarded(&self, counters: DecoderCounters) {
+        self.unrecognized_bytes
+            .fetch_add(counters.unrecognized_bytes, Ordering::Relaxed);
+        self.short_packets
+            .fetch_add(counters.short_packets, Ordering::Relaxed);
+        self.corrupted_packets
+            .fetch_add(counters.corrupted_packets, Ordering::Relaxed);
+    }
+
+    /// Sets the labels that replace the circuit names, keyed by the name on the panel. The
+    /// keys are matched ignoring case and spaces, so "AUX1" renames "AUX 1".
+    pub fn set_device_names(&mut self, device_names: HashMap<String, String>) {
+        self.device_names = device_names
+            .into_iter()
+            .map(|(name, label)| (normalize_name(&name), label))
+            .collect();
+        self.apply_circuit_config();
+    }
+
+    /// Asks the panel for the circuit definitions, the custom names, the remote layout, the
+    /// schedules and the equipment. The answers update the state as they arrive.
+    pub fn request_configuration(&mut self) {
+        self.configuration_requested = true;
+        for (request, response, argument) in self.board.configuration_requests() {
+            self.request_from_panel(request, response, argument);
         }
+    }
+
+    /// Selects the panel family, None starts with EasyTouch and follows the traffic when it
+    /// shows another one.
+    pub fn set_board(&mut self, kind: Option<BoardKind>) {
+        match kind {
+            Some(kind) => self.switch_board(kind),
+            None => self.board_detection = true,
+        }
+    }
 
+    /// Uses the default address of the board as ours, also after the board is detected.
+    pub fn use_board_controller_id(&mut self) {
+        self.board_controller_id = true;
+        self.controller_id = self.board.default_controller_id();
     }
 
-    /// Checks
+    pub fn get_board(&self) -> BoardKind {
+        self.board.kind()
+    }
 
-    // Changes a state of a control. Returns back True if it was changed, false if it was not
-    // changed yet.
-    pub fn change_circuit(&mut self, control_name: &str, state: bool) -> bool {
-        if control_name == "pool" {
-          //  self.system_state.pool_on = state;
+    fn switch_board(&mut self, kind: BoardKind) {
+        self.board_detection = false;
+        if self.board.kind() == kind {
+            return;
+        }
+        info!("Switching to the {:?} board", kind);
+        let previous = std::mem::replace(&mut self.board, kind.board());
+        if self.board.is_experimental() {
+            warn!(
+                "The {:?} layout is not verified, circuit changes are refused",
+                kind
+            );
+        }
+        self.system_state =
+            SystemState::with_circuits((self.board.status_layout().default_circuits)());
+        self.apply_circuit_config();
+        if self.board_controller_id {
+            self.controller_id = self.board.default_controller_id();
+        }
+        if self.configuration_requested {
+            // The new panel would not answer the requests of the previous one.
+            let mut cancelled = Vec::new();
+            for (_, response, _) in previous.configuration_requests() {
+                cancelled.extend(self.commands.cancel(response));
+            }
+            self.outgoing
+                .retain(|packet| !cancelled.contains(&packet.id));
+            self.request_configuration();
         }
-        if control_name == "spa" {
-          //  self.system_state.spa_on = state;
+    }
+
+    // A frame from us to the panel.
+    fn panel_frame(&self, action: u8, payload: &[u8]) -> Result<Vec<u8>, PoolError> {
+        message::ProtocolPacket::encode_packet(
+            0x01,
+            message::PANEL_ADDRESS,
+            self.controller_id,
+            action,
+            payload,
+        )
+    }
+
+    fn request_from_panel(&mut self, request: u8, response: u8, argument: u8) {
+        let ack_argument = answer_has_argument(response).then_some(argument);
+        match self.panel_frame(request, &[argument]) {
+            Ok(packet) => {
+                self.queue_command_for(packet, message::PANEL_ADDRESS, response, ack_argument);
+            }
+            Err(e) => error!("Request {:#04x} not sent: {}", request, e),
         }
-        let mut _packet = vec![0x0];
-        true
+    }
+
+    /// Writes a schedule to its slot on the panel, rejected if the schedule is not valid.
+    /// The slot is read back afterwards to update the cached schedules.
+    pub fn set_schedule(&mut self, schedule: &Schedule) -> Result<CommandHandle, PoolError> {
+        if !schedule.is_valid() {
+            return Err(rejected(format!("Invalid schedule {:?}", schedule)));
+        }
+        self.write_schedule(schedule.id, schedule.encode_payload())
+    }
+
+    /// Clears a schedule slot, rejected if there is no such slot.
+    pub fn delete_schedule(&mut self, id: u8) -> Result<CommandHandle, PoolError> {
+        if !(1..=schedule::MAX_SCHEDULES).contains(&id) {
+            return Err(rejected(format!("Unknown schedule {}", id)));
+        }
+        self.write_schedule(id, Schedule::encode_empty_payload(id))
+    }
+
+    /// The first schedule slot that is not used.
+    pub fn free_schedule_id(&self) -> Option<u8> {
+        (1..=schedule::MAX_SCHEDULES).find(|id| !self.schedules.contains_key(id))
+    }
+
+    fn write_schedule(&mut self, id: u8, payload: Vec<u8>) -> Result<CommandHandle, PoolError> {
+        let packet = self.panel_frame(schedule::SET_SCHEDULE_ACTION, &payload)?;
+        let handle = self.queue_command(
+            packet,
+            message::PANEL_ADDRESS,
+            schedule::SET_SCHEDULE_ACTION,
+        );
+        self.request_from_panel(schedule::SCHEDULE_REQUEST, schedule::SCHEDULE_ACTION, id);
+        Ok(handle)
+    }
+
+    // A response to our request finishes it.
+    fn acknowledge_response(
+        &mut self,
+        source: u8,
+        destination: u8,
+        action: u8,
+        argument: Option<u8>,
+    ) {
+        if destination == self.controller_id {
+            self.command_acknowledged(source, action, argument);
+        }
+    }
+
+    // Finishes the command waiting for this answer, its frame may be queued again already.
+    fn command_acknowledged(&mut self, source: u8, action: u8, argument: Option<u8>) {
+        if let Some(id) = self.commands.acknowledged(source, action, argument) {
+            self.outgoing.retain(|packet| packet.id != id);
+        }
+    }
+
+    // Brings the circuit table in line with the panel configuration and the labels.
+    fn apply_circuit_config(&mut self) {
+        for definition in self.panel_config.circuits.values() {
+            if definition.function == CircuitFunction::NotUsed {
+                self.system_state.remove_circuit(definition.number);
+                continue;
+            }
+            let circuit = self.system_state.define_circuit(definition.number);
+            circuit.function = definition.function;
+            circuit.freeze_protect = definition.freeze_protect;
+        }
+        for circuit in self.system_state.circuits_mut() {
+            let name = self
+                .panel_config
+                .circuit_name(circuit.number)
+                .unwrap_or_else(|| (self.board.status_layout().new_circuit)(circuit.number).name);
+            circuit.name = match self.device_names.get(&normalize_name(&name)) {
+                Some(label) => label.clone(),
+                None => name,
+            };
+        }
+    }
+
+    /// Keeps the panel clock in sync with the host, see CLOCK_DRIFT_LIMIT.
+    pub fn set_clock_sync(&mut self, enabled: bool) {
+        self.clock_sync = enabled;
+    }
+
+    /// Queues a command that sets the panel clock, the DST setting of the panel is kept.
+    pub fn set_panel_clock(&mut self, time: DateTime<Local>) -> Result<CommandHandle, PoolError> {
+        let auto_dst = self.clock.as_ref().is_none_or(|clock| clock.auto_dst);
+        let payload = PanelClock::from_time(&time, auto_dst).encode_payload();
+        let packet = self.panel_frame(clock::SET_CLOCK_ACTION, &payload)?;
+        self.clock_set_at = Some(Instant::now());
+        Ok(self.queue_command(packet, message::PANEL_ADDRESS, clock::SET_CLOCK_ACTION))
+    }
+
+    fn check_clock_drift(&mut self, panel_clock: &PanelClock, now: DateTime<Local>) {
+        if !self.clock_sync || self.commands.is_pending(clock::SET_CLOCK_ACTION) {
+            return;
+        }
+        if self
+            .clock_set_at
+            .is_some_and(|set_at| set_at.elapsed() < CLOCK_SET_INTERVAL)
+        {
+            return;
+        }
+        let drift = (panel_clock.time - now.naive_local()).abs();
+        if drift > CLOCK_DRIFT_LIMIT {
+            info!(
+                "Panel clock {} is off by {}, setting it",
+                panel_clock.time, drift
+            );
+            if let Err(e) = self.set_panel_clock(now) {
+                error!("Panel clock not set: {}", e);
+            }
+        }
+    }
+
+    /// Makes us the chlorinator master: the output in percent is sent to the chlorinator
+    /// periodically, SUPER_CHLORINATE_OUTPUT turns on super chlorination.
+    pub fn set_chlorinator_output(&mut self, output: u8) {
+        self.chlorinator_output = Some((output, None));
+    }
+
+    /// Queues a command to be sent when the bus is idle. It is finished when a packet with
+    /// `ack_action` arrives from `ack_source`, the handle receives the outcome.
+    pub fn queue_command(
+        &mut self,
+        frame: Vec<u8>,
+        ack_source: u8,
+        ack_action: u8,
+    ) -> CommandHandle {
+        self.queue_command_for(frame, ack_source, ack_action, None)
+    }
+
+    // Like queue_command, only the acknowledgement starting with `ack_argument` finishes it.
+    fn queue_command_for(
+        &mut self,
+        frame: Vec<u8>,
+        ack_source: u8,
+        ack_action: u8,
+        ack_argument: Option<u8>,
+    ) -> CommandHandle {
+        debug!("Queued packet {:?}", frame);
+        let handle = self
+            .commands
+            .add(frame.clone(), ack_source, ack_action, ack_argument);
+        self.outgoing.push_back(OutgoingPacket {
+            id: handle.id,
+            frame,
+            collisions: 0,
+        });
+        handle
+    }
+
+    /// Takes the next packet to transmit.
+    pub fn next_outgoing(&mut self) -> Option<OutgoingPacket> {
+        self.outgoing.pop_front()
+    }
+
+    /// Puts a packet that collided back in front of the queue.
+    pub fn retry_outgoing(&mut self, packet: OutgoingPacket) {
+        self.outgoing.push_front(packet);
+    }
+
+    /// Called by the port thread after it is done with a packet from the queue.
+    pub fn transmit_complete(&mut self, packet: OutgoingPacket, result: TransmitResult) {
+        match result {
+            TransmitResult::Sent => self.commands.sent(packet.id, Instant::now()),
+            TransmitResult::Failed => self.commands.transmit_failed(packet.id),
+        }
+    }
+
+    /// Called by the port thread when the bus is idle: sends again the commands that were
+    /// not acknowledged in time and keeps the remote controlled pumps with us.
+    pub fn poll_timers(&mut self, now: Instant) {
+        self.expire_commands(now);
+        let due: Vec<(u8, PumpCommand)> = self
+            .remote_pumps
+            .iter()
+            .filter(|(_, (_, sent_at))| now.duration_since(*sent_at) >= PUMP_KEEP_ALIVE)
+            .map(|(address, (command, _))| (*address, *command))
+            .collect();
+        for (address, command) in due {
+            debug!("Keeping pump {:#04x} under remote control", address);
+            if let Err(e) = self.send_pump_command(address, command, now) {
+                error!("Pump {:#04x} not kept under remote control: {}", address, e);
+            }
+        }
+        if let Some((output, sent_at)) = self.chlorinator_output {
+            let due =
+                sent_at.is_none_or(|sent_at| now.duration_since(sent_at) >= CHLORINATOR_INTERVAL);
+            if due && !self.commands.is_pending(chlorinator::STATUS_ACTION) {
+                self.chlorinator_output = Some((output, Some(now)));
+                let frame = chlorinator::encode_packet(
+                    chlorinator::CHLORINATOR_ADDRESS,
+                    chlorinator::SET_OUTPUT_ACTION,
+                    &[output],
+                );
+                self.queue_command(
+                    frame,
+                    chlorinator::CHLORINATOR_ADDRESS,
+                    chlorinator::STATUS_ACTION,
+                );
+            }
+        }
+    }
+
+    /// Sends again the commands that were not acknowledged in time and drops the ones that
+    /// ran out of time. Also called by the port thread while there is no connection.
+    pub fn expire_commands(&mut self, now: Instant) {
+        for (id, frame) in self.commands.check_timeouts(now) {
+            self.outgoing.push_back(OutgoingPacket {
+                id,
+                frame,
+                collisions: 0,
+            });
+        }
+        let commands = &self.commands;
+        self.outgoing.retain(|packet| commands.is_active(packet.id));
+    }
+
+    /// Called by the port thread when the connection to the bus is lost, the commands in
+    /// flight are failed.
+    pub fn connection_lost(&mut self) {
+        self.commands.fail_all();
+        self.outgoing.clear();
+    }
+
+    /// Queues the packets of a pump command, `pump_id` is 1 for the first pump. Rejected if
+    /// the pump or the command values are not valid.
+    pub fn pump_command(
+        &mut self,
+        pump_id: u8,
+        command: PumpCommand,
+    ) -> Result<Vec<CommandHandle>, PoolError> {
+        let Some(address) = PumpState::pump_address(pump_id) else {
+            return Err(rejected(format!("Unknown pump {}", pump_id)));
+        };
+        if !command.is_valid() {
+            return Err(rejected(format!("Invalid pump command {:?}", command)));
+        }
+        self.send_pump_command(address, command, Instant::now())
+    }
+
+    fn send_pump_command(
+        &mut self,
+        address: u8,
+        command: PumpCommand,
+        now: Instant,
+    ) -> Result<Vec<CommandHandle>, PoolError> {
+        let frames = command.encode(address, self.controller_id)?;
+        if command == PumpCommand::Release {
+            self.remote_pumps.remove(&address);
+        } else {
+            self.remote_pumps.insert(address, (command, now));
+        }
+        Ok(frames
+            .into_iter()
+            .map(|(frame, ack_action)| self.queue_command(frame, address, ack_action))
+            .collect())
+    }
+
+    /// Addresses of the pumps we control instead of the panel.
+    pub fn get_remote_pumps(&self) -> Vec<u8> {
+        self.remote_pumps.keys().copied().collect()
+    }
+
+    pub fn get_command_state(&self, id: CommandId) -> Option<CommandState> {
+        self.commands.state(id)
+    }
+
+    // Queues a command that changes a state of a circuit. Rejected if the control is not
+    // known. The new state shows up in the next status broadcast from the panel.
+    pub fn change_circuit(
+        &mut self,
+        control_name: &str,
+        state: bool,
+    ) -> Result<CommandHandle, PoolError> {
+        if self.board.is_experimental() {
+            return Err(rejected(format!(
+                "Circuit changes on {:?} are disabled until its layout is verified",
+                self.board.kind()
+            )));
+        }
+        let Some(circuit) = self.system_state.circuit_number(control_name) else {
+            return Err(rejected(format!("Unknown control {}", control_name)));
+        };
+        let action = self.board.circuit_change_action();
+        let payload =
+            self.board
+                .encode_circuit_change(&self.system_state.get_circuits(), circuit, state);
+        let packet = self.panel_frame(action, &payload)?;
+        Ok(self.queue_command(packet, message::PANEL_ADDRESS, action))
+    }
+
+    // Queues a command that changes the heat setpoint and/or mode of the pool or the spa.
+    // The command carries both bodies, so it needs the current settings from the panel,
+    // rejected until they are known.
+    pub fn set_heat(
+        &mut self,
+        body: Body,
+        setpoint: Option<u8>,
+        mode: Option<HeatMode>,
+    ) -> Result<CommandHandle, PoolError> {
+        let Some(mut settings) = self.system_state.get_heat_settings() else {
+            return Err(rejected("Heat settings are not known yet".to_string()));
+        };
+        settings.update(body, setpoint, mode);
+        let packet = self.panel_frame(heat::SET_HEAT_ACTION, &settings.encode_set_payload())?;
+        Ok(self.queue_command(packet, message::PANEL_ADDRESS, heat::SET_HEAT_ACTION))
+    }
+
+    /// Runs a theme or a color mode on the IntelliBrite lights.
+    pub fn set_light_theme(&mut self, theme: LightTheme) -> Result<CommandHandle, PoolError> {
+        let packet = self.panel_frame(light::LIGHT_COMMAND_ACTION, &theme.encode_payload())?;
+        self.pending_light_theme = Some(theme);
+        Ok(self.queue_command(packet, message::PANEL_ADDRESS, light::LIGHT_COMMAND_ACTION))
+    }
+
+    /// Sets the positions, colors and swim delays of the lights, rejected if the group is not
+    /// valid. The group is read back afterwards.
+    pub fn set_light_group(&mut self, group: &LightGroup) -> Result<CommandHandle, PoolError> {
+        if !group.is_valid() {
+            return Err(rejected(format!("Invalid light group {:?}", group)));
+        }
+        let packet = self.panel_frame(light::SET_LIGHT_GROUP_ACTION, &group.encode_payload())?;
+        let handle = self.queue_command(
+            packet,
+            message::PANEL_ADDRESS,
+            light::SET_LIGHT_GROUP_ACTION,
+        );
+        self.request_from_panel(light::LIGHT_GROUP_REQUEST, light::LIGHT_GROUP_ACTION, 0);
+        Ok(handle)
+    }
+
+    /// True while a circuit change was sent and the panel has not acknowledged it yet.
+    pub fn is_waiting_for_circuit_status_response(&self) -> bool {
+        self.commands.is_pending(self.board.circuit_change_action())
     }
 
     pub fn log_packet(&mut self, pckt: &[u8]) {
@@ -113,3 +862,559 @@ impl PoolProtocol {
     }
 }
 
+// Answers that start with the argument of their request: the circuit number, the name index
+// or the schedule id. The panel gets many of these requests in a row.
+fn answer_has_argument(response: u8) -> bool {
+    matches!(
+        response,
+        panel_config::CIRCUIT_DEFINITION_ACTION
+            | panel_config::CUSTOM_NAME_ACTION
+            | schedule::SCHEDULE_ACTION
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::command::CommandOutcome;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_change_circuit_queues_packet() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        let mut handle = protocol.change_circuit("pool", true).unwrap();
+        assert_eq!(
+            protocol.outgoing[0].frame,
+            vec![0xFF, 0x00, 0xFF, 0xA5, 0x01, 0x10, 0x24, 0x86, 0x02, 0x06, 0x01, 0x01, 0x69]
+        );
+        assert!(protocol.is_waiting_for_circuit_status_response());
+        let packet = protocol.next_outgoing().unwrap();
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        assert_eq!(
+            protocol.get_command_state(handle.id),
+            Some(CommandState::Sent)
+        );
+
+        // Acknowledgement for somebody else.
+        protocol.process_packet(&[0x01, 0x22, 0x10, 0x01, 0x01, 0x86]);
+        assert!(protocol.is_waiting_for_circuit_status_response());
+
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x01, 0x01, 0x86]);
+        assert!(!protocol.is_waiting_for_circuit_status_response());
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Acked);
+
+        assert!(matches!(
+            protocol.change_circuit("jacuzzi", true),
+            Err(PoolError::CommandRejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_pump_status_is_kept() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        let mut packet = vec![
+            0x00, 0x10, 0x61, 0x07, 0x0F, 0x0A, 0x00, 0x02, 0x04, 0x1A, 0x09, 0x92, 0x2A, 0x00,
+            0x00, 0x00, 0x00, 0x05, 0x0E, 0x21,
+        ];
+        protocol.process_packet(&packet);
+        packet[2] = 0x60;
+        protocol.process_packet(&packet);
+        packet[11] = 0x93;
+        protocol.process_packet(&packet);
+
+        let pumps = protocol.get_pumps();
+        assert_eq!(pumps.len(), 2);
+        assert_eq!((pumps[0].address, pumps[0].rpm), (0x60, 2451));
+        assert_eq!((pumps[1].address, pumps[1].rpm), (0x61, 2450));
+    }
+
+    #[test]
+    fn test_unacknowledged_command_is_resent() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(10),
+        };
+        let mut protocol = PoolProtocol::new(0x24, policy);
+        let handle = protocol.change_circuit("spa", false).unwrap();
+
+        // The first one collided and goes back in front of the queue.
+        let mut packet = protocol.next_outgoing().unwrap();
+        packet.collisions += 1;
+        protocol.retry_outgoing(packet);
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(packet.collisions, 1);
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        assert!(protocol.next_outgoing().is_none());
+
+        protocol.poll_timers(Instant::now() + Duration::from_millis(15));
+        assert_eq!(
+            protocol.get_command_state(handle.id),
+            Some(CommandState::Retried)
+        );
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(packet.id, handle.id);
+        protocol.transmit_complete(packet, TransmitResult::Failed);
+        assert_eq!(
+            protocol.get_command_state(handle.id),
+            Some(CommandState::Failed)
+        );
+    }
+
+    #[test]
+    fn test_late_ack_drops_resent_frame() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(10),
+        };
+        let mut protocol = PoolProtocol::new(0x24, policy);
+        let handle = protocol.change_circuit("spa", false).unwrap();
+        let packet = protocol.next_outgoing().unwrap();
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        protocol.poll_timers(Instant::now() + Duration::from_millis(10));
+        assert_eq!(protocol.outgoing.len(), 1);
+
+        protocol.process_packet(&[0x00, 0x24, 0x10, 0x01, 0x01, 0x86]);
+        assert_eq!(
+            protocol.get_command_state(handle.id),
+            Some(CommandState::Acked)
+        );
+        assert!(protocol.next_outgoing().is_none());
+    }
+
+    #[test]
+    fn test_command_without_bus_times_out() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(10),
+        };
+        let mut protocol = PoolProtocol::new(0x24, policy);
+        let mut handle = protocol.change_circuit("spa", false).unwrap();
+
+        // Never transmitted, nobody calls transmit_complete.
+        protocol.expire_commands(Instant::now() + Duration::from_millis(20));
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::TimedOut);
+        assert!(protocol.next_outgoing().is_none());
+
+        let mut handle = protocol.change_circuit("spa", true).unwrap();
+        protocol.connection_lost();
+        assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Failed);
+        assert!(protocol.next_outgoing().is_none());
+    }
+
+    #[test]
+    fn test_pump_remote_control() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        assert!(protocol.pump_command(0, PumpCommand::Stop).is_err());
+        assert!(protocol
+            .pump_command(1, PumpCommand::Gpm { value: 200 })
+            .is_err());
+
+        let mut handles = protocol
+            .pump_command(2, PumpCommand::Rpm { value: 1800 })
+            .unwrap();
+        assert_eq!(handles.len(), 3);
+        assert_eq!(protocol.get_remote_pumps(), vec![0x61]);
+        while let Some(packet) = protocol.next_outgoing() {
+            assert_eq!(packet.frame[5..7], [0x61, 0x24]);
+            protocol.transmit_complete(packet, TransmitResult::Sent);
+        }
+
+        // The panel acknowledging with the same action does not confirm a pump command.
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x01, 0x01, 0x86]);
+        assert_eq!(
+            protocol.get_command_state(handles[1].id),
+            Some(CommandState::Sent)
+        );
+        protocol.process_packet(&[0x00, 0x24, 0x61, 0x04, 0x01, 0xFF]);
+        protocol.process_packet(&[0x00, 0x24, 0x61, 0x01, 0x02, 0x07, 0x08]);
+        protocol.process_packet(&[0x00, 0x24, 0x61, 0x06, 0x01, 0x0A]);
+        for handle in handles.iter_mut() {
+            assert_eq!(handle.outcome.try_recv().unwrap(), CommandOutcome::Acked);
+        }
+
+        // The command is repeated while the pump is ours.
+        protocol.poll_timers(Instant::now() + PUMP_KEEP_ALIVE);
+        assert_eq!(protocol.outgoing.len(), 3);
+        protocol.outgoing.clear();
+
+        protocol.pump_command(2, PumpCommand::Release).unwrap();
+        assert!(protocol.get_remote_pumps().is_empty());
+        assert_eq!(protocol.outgoing.len(), 1);
+    }
+
+    #[test]
+    fn test_chlorinator_master() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        let now = Instant::now();
+        protocol.poll_timers(now);
+        assert!(protocol.outgoing.is_empty());
+
+        protocol.set_chlorinator_output(30);
+        protocol.poll_timers(now);
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(
+            packet.frame,
+            vec![0x10, 0x02, 0x50, 0x11, 0x1E, 0x91, 0x10, 0x03]
+        );
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+
+        protocol.process_chlorinator_packet(&[0x00, 0x12, 0x3C, 0x00]);
+        let state = protocol.get_chlorinator().unwrap();
+        assert_eq!((state.salt_ppm, state.output_percent), (3000, 30));
+        assert!(!protocol.commands.is_pending(chlorinator::STATUS_ACTION));
+
+        protocol.poll_timers(now + Duration::from_secs(1));
+        assert!(protocol.outgoing.is_empty());
+        protocol.poll_timers(now + CHLORINATOR_INTERVAL);
+        assert_eq!(protocol.outgoing.len(), 1);
+    }
+
+    #[test]
+    fn test_clock_drift_sets_panel_clock() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        let broadcast = |minutes: i64| {
+            let time = Local::now() - chrono::TimeDelta::minutes(minutes);
+            let mut packet = vec![0x01, 0x0F, 0x10, 0x05, 0x08];
+            packet.extend(PanelClock::from_time(&time, true).encode_payload());
+            packet
+        };
+        protocol.process_packet(&broadcast(10));
+        assert!(protocol.get_clock().unwrap().auto_dst);
+        assert!(protocol.outgoing.is_empty());
+
+        protocol.set_clock_sync(true);
+        protocol.process_packet(&broadcast(1));
+        assert!(protocol.outgoing.is_empty());
+        protocol.process_packet(&broadcast(10));
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(packet.frame[4..9], [0x01, 0x10, 0x24, 0x85, 0x08]);
+
+        // Only once while it is not acknowledged.
+        protocol.process_packet(&broadcast(10));
+        assert!(protocol.outgoing.is_empty());
+    }
+
+    #[test]
+    fn test_set_heat() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        assert!(protocol.set_heat(Body::Pool, Some(84), None).is_err());
+
+        // The answer to our request at start.
+        protocol.request_configuration();
+        let packet = protocol.next_outgoing().unwrap();
+        let id = packet.id;
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        protocol.outgoing.clear();
+        protocol.process_packet(&[
+            0x01, 0x24, 0x10, 0x08, 0x0D, 0x52, 0x52, 0x48, 0x52, 0x66, 0x06, 0x00, 0x00, 0x00,
+            0x53, 0x00, 0x00, 0x00,
+        ]);
+        assert_eq!(protocol.get_command_state(id), Some(CommandState::Acked));
+        // The next status broadcast keeps the heat settings.
+        protocol.process_packet(&[
+            0x01, 0x0F, 0x10, 0x02, 0x1D, 0x0D, 0x1D, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x52, 0x52, 0x00, 0x00, 0x48, 0x53, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        let settings = protocol.get_state().get_heat_settings().unwrap();
+        assert_eq!(
+            (settings.pool_setpoint, settings.spa_mode),
+            (82, HeatMode::Heater)
+        );
+
+        protocol
+            .set_heat(Body::Pool, Some(84), Some(HeatMode::Heater))
+            .unwrap();
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..13],
+            [0x01, 0x10, 0x24, 0x88, 0x04, 84, 102, 0x05, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_learn_circuit_config() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        protocol.set_device_names(HashMap::from([(
+            "AUX1".to_string(),
+            "Edge Pump".to_string(),
+        )]));
+        assert_eq!(protocol.get_state().circuit_number("Edge Pump"), Some(2));
+
+        protocol.request_configuration();
+        // The heat settings are asked for first.
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..10],
+            [0x01, 0x10, 0x24, 0xC8, 0x01, 0x00]
+        );
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(packet.frame[4..10], [0x01, 0x10, 0x24, 0xCB, 0x01, 0x01]);
+        let id = packet.id;
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        assert_eq!(
+            protocol.outgoing.len(),
+            board::EasyTouch.configuration_requests().len() - 2
+        );
+
+        // Circuit 1 is the pool light, 3 has the custom name 0 and 8 is not used.
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x01, 0x10, 63, 0x00, 0x00]);
+        assert_eq!(protocol.get_command_state(id), Some(CommandState::Acked));
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x03, 0x40, 200, 0x00, 0x00]);
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x08, 0x13, 53, 0x00, 0x00]);
+        let mut custom_name = vec![0x01, 0x24, 0x10, 0x0A, 0x0C];
+        custom_name.extend(panel_config::encode_custom_name(0, "SLIDE"));
+        protocol.process_packet(&custom_name);
+        // The aux 1 label from the configuration wins over the panel name.
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x02, 0x00, 3, 0x00, 0x00]);
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x06, 0x02, 61, 0x00, 0x00]);
+
+        let state = protocol.get_state();
+        let circuits = state.get_circuits();
+        assert_eq!(circuits.len(), 7);
+        assert_eq!(circuits[0].name, "POOL LIGHT");
+        assert_eq!(circuits[0].function, CircuitFunction::IntelliBrite);
+        assert_eq!(circuits[1].name, "Edge Pump");
+        assert_eq!(
+            (circuits[2].name.as_str(), circuits[2].freeze_protect),
+            ("SLIDE", true)
+        );
+        assert_eq!(state.circuit_number("feature3"), None);
+
+        // The controls keep their ids, the names work too.
+        assert_eq!(circuits[5].name, "POOL");
+        assert_eq!(state.circuit_number("pool"), Some(6));
+        assert_eq!(state.circuit_number("aux1"), Some(2));
+        assert_eq!(state.circuit_number("edge pump"), Some(2));
+        assert_eq!(state.get_controls_state()[1], ("aux1".to_string(), false));
+        protocol.outgoing.clear();
+        protocol.change_circuit("pool", true).unwrap();
+        assert_eq!(protocol.next_outgoing().unwrap().frame[9..11], [0x06, 0x01]);
+    }
+
+    #[test]
+    fn test_lost_configuration_answer() {
+        let policy = CommandPolicy {
+            retries: 1,
+            timeout: Duration::from_millis(10),
+        };
+        let mut protocol = PoolProtocol::new(0x24, policy);
+        protocol.request_configuration();
+        let mut circuit_requests = HashMap::new();
+        while let Some(packet) = protocol.next_outgoing() {
+            if packet.frame[7] == panel_config::CIRCUIT_DEFINITION_REQUEST {
+                circuit_requests.insert(packet.frame[9], packet.id);
+            }
+            protocol.transmit_complete(packet, TransmitResult::Sent);
+        }
+
+        // The answer about circuit 1 is lost, the one about circuit 2 does not finish its request.
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x0B, 0x05, 0x02, 0x00, 3, 0x00, 0x00]);
+        assert_eq!(
+            protocol.get_command_state(circuit_requests[&1]),
+            Some(CommandState::Sent)
+        );
+        assert_eq!(
+            protocol.get_command_state(circuit_requests[&2]),
+            Some(CommandState::Acked)
+        );
+
+        protocol.poll_timers(Instant::now() + Duration::from_millis(15));
+        let resent: Vec<_> = std::iter::from_fn(|| protocol.next_outgoing())
+            .filter(|packet| packet.frame[7] == panel_config::CIRCUIT_DEFINITION_REQUEST)
+            .map(|packet| packet.frame[9])
+            .collect();
+        assert_eq!(resent.first(), Some(&1));
+        assert!(!resent.contains(&2));
+    }
+
+    #[test]
+    fn test_schedules() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        protocol.process_packet(&[
+            0x01, 0x24, 0x10, 0x11, 0x07, 0x01, 0x06, 0x08, 0x00, 0x11, 0x1E, 0x3E,
+        ]);
+        protocol.process_packet(&[
+            0x01, 0x24, 0x10, 0x11, 0x07, 0x02, 0x01, 0x19, 0x00, 0x02, 0x0F, 0x00,
+        ]);
+        assert_eq!(protocol.get_schedules().len(), 2);
+        assert_eq!(protocol.free_schedule_id(), Some(3));
+
+        let mut schedule = protocol.get_schedules()[0].clone();
+        schedule.circuit = 1;
+        protocol.set_schedule(&schedule).unwrap();
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..16],
+            [0x01, 0x10, 0x24, 0x91, 0x07, 0x01, 0x01, 0x08, 0x00, 0x11, 0x1E, 0x3E]
+        );
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..10],
+            [0x01, 0x10, 0x24, 0xD1, 0x01, 0x01]
+        );
+
+        schedule.id = 13;
+        assert!(protocol.set_schedule(&schedule).is_err());
+        assert!(protocol.delete_schedule(0).is_err());
+
+        // The panel reports the slot empty after a delete.
+        protocol.delete_schedule(2).unwrap();
+        protocol.process_packet(&[
+            0x01, 0x24, 0x10, 0x11, 0x07, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        assert_eq!(protocol.get_schedules().len(), 1);
+    }
+
+    #[test]
+    fn test_equipment_config() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x22, 0x03, 0x01, 0x06, 0x03]);
+        let equipment = protocol.get_equipment_config();
+        assert_eq!(
+            equipment.heaters,
+            vec![equipment::HeaterType::Gas, equipment::HeaterType::Solar]
+        );
+        assert!(equipment.valves.is_none());
+
+        // A wireless remote turns on solar freeze protection, we read the result back.
+        protocol.process_packet(&[0x01, 0x10, 0x22, 0xA2, 0x03, 0x05, 0x06, 0x03]);
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..10],
+            [0x01, 0x10, 0x24, 0xE2, 0x01, 0x00]
+        );
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x22, 0x03, 0x05, 0x06, 0x03]);
+        assert!(
+            protocol
+                .get_equipment_config()
+                .solar
+                .unwrap()
+                .freeze_protect
+        );
+    }
+
+    #[test]
+    fn test_light_theme() {
+        let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+        protocol.set_light_theme(LightTheme::Caribbean).unwrap();
+        let packet = protocol.next_outgoing().unwrap();
+        assert_eq!(
+            packet.frame[4..11],
+            [0x01, 0x10, 0x24, 0x60, 0x02, 179, 0x00]
+        );
+        protocol.transmit_complete(packet, TransmitResult::Sent);
+        assert_eq!(protocol.get_lights().theme, None);
+        protocol.process_packet(&[0x01, 0x24, 0x10, 0x01, 0x01, 0x60]);
+        assert_eq!(protocol.get_lights().theme, Some(LightTheme::Caribbean));
+
+        // A wall remote starts color swim.
+        protocol.process_packet(&[0x01, 0x10, 0x22, 0x60, 0x02, 144, 0x00]);
+        assert_eq!(protocol.get_lights().theme, Some(LightTheme::ColorSwim));
+    }
+
+    #[test]
+    fn test_board_detection() {
+        // EasyTouch is asked for its configuration right away.
+        let mut protocol = PoolProtocol::new(0, CommandPolicy::default());
+        protocol.set_board(None);
+        protocol.use_board_controller_id();
+        protocol.request_configuration();
+        assert_eq!(protocol.get_board(), BoardKind::EasyTouch);
+        assert_eq!(
+            protocol.next_outgoing().unwrap().frame[4..10],
+            [0x01, 0x10, 0x24, 0xC8, 0x01, 0x00]
+        );
+        protocol.process_packet(&[
+            0x01, 0x0F, 0x10, 0x05, 0x08, 0x09, 0x29, 0x01, 0x12, 0x0A, 0x1A, 0x00, 0x01,
+        ]);
+        assert_eq!(protocol.get_board(), BoardKind::EasyTouch);
+        assert!(protocol.next_outgoing().is_some());
+
+        // The extended status of IntelliCenter drops the EasyTouch requests, there is nothing
+        // to ask IntelliCenter at start.
+        let mut protocol = PoolProtocol::new(0, CommandPolicy::default());
+        protocol.set_board(None);
+        protocol.use_board_controller_id();
+        protocol.request_configuration();
+        protocol.process_packet(&[0x01, 0x0F, 0x10, 0xCC, 0x01, 0x00]);
+        assert_eq!(protocol.get_board(), BoardKind::IntelliCenter);
+        assert!(protocol.next_outgoing().is_none());
+        // Experimental: nothing is sent to IntelliCenter.
+        assert!(matches!(
+            protocol.change_circuit("pool", true),
+            Err(PoolError::CommandRejected(_))
+        ));
+        assert!(protocol.next_outgoing().is_none());
+    }
+
+    #[test]
+    fn test_error_counters() {
+        let protocol_errors = |packets: &[&[u8]]| {
+            let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+            for packet in packets {
+                protocol.process_packet(packet);
+            }
+            protocol.get_error_counters()
+        };
+        // A status without the temperatures, an unknown version and an action we don't decode.
+        assert_eq!(
+            protocol_errors(&[
+                &[0x01, 0x0F, 0x10, 0x02, 0x03, 0x0A, 0x1A, 0x20],
+                &[0x07, 0x10, 0x24, 0x86, 0x00],
+                &[0x01, 0x10, 0x24, 0x77, 0x00],
+            ]),
+            ErrorCounters {
+                short_packets: 1,
+                unknown_protocol: 2,
+                ..Default::default()
+            }
+        );
+        // The clock of the 31st of February.
+        let clock = [
+            0x01, 0x0F, 0x10, 0x05, 0x08, 0x09, 0x29, 0x01, 0x1F, 0x02, 0x1A, 0x00, 0x01,
+        ];
+        assert_eq!(protocol_errors(&[&clock]).corrupted_packets, 1);
+    }
+
+    // Actions we decode and the addresses that change how they are decoded.
+    const ACTIONS: [u8; 31] = [
+        0x01, 0x02, 0x04, 0x05, 0x06, 0x07, 0x08, 0x0A, 0x0B, 0x11, 0x12, 0x1D, 0x1E, 0x21, 0x22,
+        0x27, 0x60, 0x85, 0x86, 0x88, 0x91, 0x9D, 0xA2, 0xA4, 0xA7, 0xA8, 0xCC, 0xD1, 0xD2, 0xE1,
+        0xE7,
+    ];
+    const ADDRESSES: [u8; 7] = [0x0F, 0x10, 0x21, 0x24, 0x60, 0x61, 0x90];
+
+    fn bus_packet() -> impl Strategy<Value = Vec<u8>> {
+        let field =
+            |values: &'static [u8]| prop_oneof![proptest::sample::select(values), any::<u8>()];
+        (
+            0u8..=2,
+            field(&ADDRESSES),
+            field(&ADDRESSES),
+            field(&ACTIONS),
+            proptest::collection::vec(any::<u8>(), 0..64),
+        )
+            .prop_map(|(version, dest, source, action, payload)| {
+                let mut packet = vec![version, dest, source, action, payload.len() as u8];
+                packet.extend(payload);
+                packet
+            })
+    }
+
+    proptest! {
+        // Whatever is on the bus, the reader thread keeps going.
+        #[test]
+        fn test_bus_noise_does_not_panic(
+            packets in proptest::collection::vec(
+                prop_oneof![bus_packet(), proptest::collection::vec(any::<u8>(), 0..16)],
+                1..8,
+            ),
+            detect in any::<bool>(),
+        ) {
+            let mut protocol = PoolProtocol::new(0x24, CommandPolicy::default());
+            protocol.set_board(if detect { None } else { Some(BoardKind::IntelliCenter) });
+            for packet in &packets {
+                protocol.process_packet(packet);
+                protocol.process_chlorinator_packet(packet);
+            }
+            protocol.poll_timers(Instant::now());
+            while protocol.next_outgoing().is_some() {}
+        }
+    }
+}
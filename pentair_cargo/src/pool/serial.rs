@@ -1,16 +1,39 @@
-use crate::pool::{message, PoolProtocolRW};
 use crate::config;
-use log::{debug, error, trace};
+use crate::error::PoolError;
+use crate::pool::capture::{CaptureTransport, CaptureWriter};
+use crate::pool::frame::{Frame, FrameDecoder};
+use crate::pool::protocol::{OutgoingPacket, TransmitResult};
+use crate::pool::transport::{is_timeout, Transport};
+use crate::pool::PoolProtocolRW;
+use log::{debug, error, info, trace, warn};
 use serial::{self, SerialPort};
-use std::io::{ErrorKind, Read, Write, BufWriter};
-use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
+/// How long the bus has to be quiet before we start transmitting.
+const BUS_IDLE_TIME: Duration = Duration::from_millis(100);
+/// Backoff after the first collision, doubled on every next one.
+const COLLISION_BACKOFF: Duration = Duration::from_millis(50);
+/// Give up on a packet after this many collisions.
+const MAX_COLLISIONS: u32 = 5;
+/// Bytes taken from the port at once, the decoder keeps the frames that span reads.
+const READ_CHUNK_SIZE: usize = 64;
+/// Wait between attempts to open the port or connect to the bridge.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
 
 /// Creates a serial port from the  configuration.
 pub fn serial_port(
     parameters: &config::config_json::PortParameters,
-) -> Result<serial::SystemPort, serial::Error> {
-    let port_name = &parameters.port_name;
+) -> Result<serial::SystemPort, PoolError> {
+    let Some(port_name) = &parameters.port_name else {
+        return Err(PoolError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither port_name nor net_address is configured",
+        )));
+    };
 
     let settings = serial::PortSettings {
         baud_rate: serial::BaudRate::from_speed(parameters.baud_rate),
@@ -25,130 +48,351 @@ pub fn serial_port(
     Ok(port)
 }
 
+/// Opens the connection to the bus: TCP to a network bridge if net_address is set,
+/// otherwise the serial port.
+pub fn open_transport(
+    parameters: &config::config_json::PortParameters,
+) -> Result<Box<dyn Transport>, PoolError> {
+    match &parameters.net_address {
+        Some(address) => {
+            let stream = TcpStream::connect(address)?;
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        None => Ok(Box::new(serial_port(parameters)?)),
+    }
+}
 
-/// Status from processing serial input.
-#[derive(PartialEq)]
-enum HeaderScan {
-    BusAvailable,
-    GoodHeader,
+/// Result of writing a frame to the bus.
+#[derive(PartialEq, Debug)]
+enum Transmission {
+    Clean,
+    Collision,
 }
 
-fn scan_for_header(port: &mut serial::SystemPort) -> Result<HeaderScan, serial::Error> {
-    const HEADER: [u8; 4] = [0xFF, 0x00, 0xFF, 0xA5];
-    let mut byte = [0; 1];
-    let mut buffer = Vec::with_capacity(HEADER.len());
-    if let Err(e) = port.read_exact(&mut byte[..]) {
-        return if e.kind() == ErrorKind::TimedOut {
-            Ok(HeaderScan::BusAvailable)
-        } else {
-            Err(e.into())
-        };
+/// Writes the frame and, if the adapter echoes, reads it back. A different echo means
+/// somebody else was talking at the same time.
+fn transmit<T: Read + Write>(
+    port: &mut T,
+    frame: &[u8],
+    local_echo: bool,
+) -> Result<Transmission, PoolError> {
+    port.write_all(frame)?;
+    port.flush()?;
+    if !local_echo {
+        return Ok(Transmission::Clean);
     }
-    trace!("Reading a header from the port");
-    loop {
-        debug!("Read byte {:?} from the port", byte[0]);
-        buffer.push(byte[0]);
-        if buffer.len() == HEADER.len() {
-            if buffer == HEADER {
-                break;
-            } else {
-                buffer.remove(0);
-                //self.unrecognized_bytes.fetch_add(1, Ordering::Relaxed);
-            }
+    let mut echo = vec![0; frame.len()];
+    match port.read_exact(&mut echo[..]) {
+        Ok(()) if echo == frame => Ok(Transmission::Clean),
+        Ok(()) => {
+            debug!("Echo {:?} differs from {:?}", echo, frame);
+            Ok(Transmission::Collision)
         }
-        port.read_exact(&mut byte[..])?;
+        Err(e) if is_timeout(&e) => Ok(Transmission::Collision),
+        Err(e) => Err(e.into()),
     }
-    Ok(HeaderScan::GoodHeader)
 }
 
-
-fn read_packet(port: &mut serial::SystemPort) -> Result<Vec<u8>, serial::Error> {
-    const USUAL_PACKET_SIZE: usize = 32;
-    let mut buffer: Vec<u8> = Vec::with_capacity(USUAL_PACKET_SIZE);
-    let mut byte: [u8; 1] = [0];
-    for _ in 0..5 {
-        port.read_exact(&mut byte[..])?;
-        buffer.push(byte[0]);
-    }
-    let to_read_len = buffer[4] as usize;
-    for _ in 0..to_read_len {
-        port.read_exact(&mut byte[..])?;
-        buffer.push(byte[0]);
-    }
-
-    // Checksum
-    port.read_exact(&mut byte[..])?;
-    let mut checksum: u16 = 256 * (byte[0] as u16);
-    port.read_exact(&mut byte[..])?;
-    checksum += byte[0] as u16;
-    checksum -= 0xa5; // Because it has the last byte of the header
-    debug!("Read checksum {}", checksum);
-    for b in buffer.iter() {
-        checksum -= *b as u16;
-    }
-    debug!("Rest of checksum {}", checksum);
-    if checksum != 0 {
-        //self.corrupted_packets.fetch_add(1, Ordering::Relaxed);
-        return Err(serial::Error::new(
-            serial::ErrorKind::InvalidInput,
-            "Checksum error",
-        ));
-    }
-
-    Ok(buffer)
+/// Decides when the port thread may write to the bus.
+struct TransmitScheduler {
+    // Last time we saw anything on the bus.
+    last_activity: Instant,
+    // Don't transmit before this time after a collision.
+    backoff_until: Instant,
+    local_echo: bool,
 }
 
+impl TransmitScheduler {
+    fn new(local_echo: bool) -> Self {
+        let now = Instant::now();
+        TransmitScheduler {
+            last_activity: now,
+            backoff_until: now,
+            local_echo,
+        }
+    }
 
-struct PacketLogger {
-    log_file: Option<String>,
-    writer: Option<BufWriter<std::fs::File>>,
-}
+    fn bus_activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
 
-impl PacketLogger {
-    fn new(log_file: Option<String>) -> Self {
-        let writer = log_file.as_ref().map(|file_path| {
-            let file = File::create(file_path).expect("Failed to open log file");
-            BufWriter::new(file)
-        });
-        PacketLogger { log_file, writer }
+    fn can_transmit(&self) -> bool {
+        let now = Instant::now();
+        now.duration_since(self.last_activity) >= BUS_IDLE_TIME && now >= self.backoff_until
+    }
+
+    /// Sends one packet from the queue if the bus has been quiet long enough.
+    fn send_waiting<T: Read + Write>(&mut self, port: &mut T, pool_protocol: &PoolProtocolRW) {
+        if !self.can_transmit() {
+            return;
+        }
+        let packet = pool_protocol.write().unwrap().next_outgoing();
+        let Some(packet) = packet else {
+            return;
+        };
+        trace!("Transmitting {:?}", packet.frame);
+        let result = transmit(port, &packet.frame, self.local_echo);
+        self.bus_activity();
+        match result {
+            Ok(Transmission::Clean) => pool_protocol
+                .write()
+                .unwrap()
+                .transmit_complete(packet, TransmitResult::Sent),
+            Ok(Transmission::Collision) => self.collision(packet, pool_protocol),
+            Err(e) => {
+                error!("Failed to transmit a packet: {}", e);
+                pool_protocol
+                    .write()
+                    .unwrap()
+                    .transmit_complete(packet, TransmitResult::Failed);
+            }
+        }
     }
-    fn log_message(&mut self, message: message::ProtocolPacket) -> Result<(), std::io::Error> {
-        if let Some(writer) = &mut self.writer {
-            let message_str = format!("{:?}\n", message);
-            writer.write_all(message_str.as_bytes())?;
-            writer.flush()?;
+
+    fn collision(&mut self, mut packet: OutgoingPacket, pool_protocol: &PoolProtocolRW) {
+        packet.collisions += 1;
+        if packet.collisions >= MAX_COLLISIONS {
+            warn!(
+                "Dropping packet {:?} after {} collisions",
+                packet.frame, packet.collisions
+            );
+            pool_protocol
+                .write()
+                .unwrap()
+                .transmit_complete(packet, TransmitResult::Failed);
+            return;
         }
-        Ok(())
+        let backoff = COLLISION_BACKOFF * (1 << packet.collisions);
+        debug!("Collision, retrying in {:?}", backoff);
+        self.backoff_until = Instant::now() + backoff;
+        pool_protocol.write().unwrap().retry_outgoing(packet);
     }
 }
 
-
-
-pub fn port_read_thread(mut port: serial::SystemPort, pool_protocol: PoolProtocolRW) {
-    trace!("Pool monitor thread started");
+/// Reads and writes packets until the connection is lost or nothing was received for the
+/// `inactivity` time.
+pub fn run_connection<T: Transport>(
+    mut port: T,
+    pool_protocol: &PoolProtocolRW,
+    local_echo: bool,
+    inactivity: Option<Duration>,
+) -> PoolError {
+    // A read that times out tells us that the bus is idle.
+    if let Err(e) = port.set_read_timeout(BUS_IDLE_TIME) {
+        return e.into();
+    }
+    let mut scheduler = TransmitScheduler::new(local_echo);
+    let mut last_received = Instant::now();
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = [0; READ_CHUNK_SIZE];
 
     loop {
-        match scan_for_header(&mut port) {
-            Ok(r) => {
-                if r == HeaderScan::BusAvailable {
-                    // TODO: process waiting messages;
-                    continue;
+        let len = match port.read(&mut buffer) {
+            Ok(0) => {
+                return PoolError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed",
+                ))
+            }
+            Ok(len) => len,
+            Err(e) if is_timeout(&e) => {
+                decoder.idle();
+                pool_protocol
+                    .read()
+                    .unwrap()
+                    .count_discarded(decoder.take_counters());
+                if inactivity.is_some_and(|limit| last_received.elapsed() >= limit) {
+                    warn!(
+                        "Nothing received from the bus for {:?}",
+                        last_received.elapsed()
+                    );
+                    return PoolError::Timeout;
                 }
+                pool_protocol.write().unwrap().poll_timers(Instant::now());
+                scheduler.send_waiting(&mut port, pool_protocol);
+                continue;
             }
             Err(e) => {
-                error!("Failed waiting for a header: {}", e)
+                let e = PoolError::from(e);
+                if e.is_connection_lost() {
+                    return e;
+                }
+                error!("Failed to read from the bus: {}", e);
+                continue;
             }
-        }
-        match read_packet(&mut port) {
-            Ok(packet) => {
+        };
+        scheduler.bus_activity();
+        last_received = Instant::now();
+        let mut pool = pool_protocol.write().unwrap();
+        decoder.push(&buffer[..len], &mut |frame| match frame {
+            Frame::Packet(packet) => {
                 trace!("Received a correct packet");
-                let mut pool = pool_protocol.write().unwrap();
-                pool.process_packet(packet.as_slice());
-            },
+                pool.process_packet(packet);
+            }
+            Frame::Chlorinator(packet) => pool.process_chlorinator_packet(packet),
+        });
+        pool.count_discarded(decoder.take_counters());
+    }
+}
+
+/// The thread that owns the connection to the bus. Opens the port or connects to the
+/// bridge and does it again whenever the connection is lost.
+pub fn port_read_thread(
+    parameters: config::config_json::PortParameters,
+    pool_protocol: PoolProtocolRW,
+) {
+    trace!("Pool monitor thread started");
+    let inactivity = match parameters.inactivity_retry_sec {
+        0 => None,
+        sec => Some(Duration::from_secs(sec as u64)),
+    };
+    let mut capture = parameters.samples_file.as_ref().and_then(|path| {
+        CaptureWriter::create(Path::new(path))
+            .map_err(|e| error!("Failed to open the capture file {}: {}", path, e))
+            .ok()
+    });
+    loop {
+        match open_transport(&parameters) {
+            Ok(port) => {
+                info!("Connected to the bus");
+                let local_echo = parameters.local_echo;
+                let e = match &mut capture {
+                    Some(writer) => run_connection(
+                        CaptureTransport::new(port, writer),
+                        &pool_protocol,
+                        local_echo,
+                        inactivity,
+                    ),
+                    None => run_connection(port, &pool_protocol, local_echo, inactivity),
+                };
+                warn!("Lost connection to the bus: {}", e);
+                pool_protocol.write().unwrap().connection_lost();
+            }
             Err(e) => {
-                error!("Failed to read packet: {}", e);
+                error!("Failed to connect to the bus: {}", e);
+                // Nothing polls the timers without a connection.
+                pool_protocol
+                    .write()
+                    .unwrap()
+                    .expire_commands(Instant::now());
+            }
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::command::{CommandPolicy, CommandState};
+    use crate::pool::protocol::PoolProtocol;
+    use crate::pool::transport::MemoryTransport;
+    use std::net::TcpListener;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn test_transmit_detects_collision() {
+        let mut port = MemoryTransport::loopback();
+        assert_eq!(
+            transmit(&mut port, &[0xA5, 0x01], true).unwrap(),
+            Transmission::Clean
+        );
+
+        // Somebody else's byte got in front of our echo.
+        port.write_all(&[0x10]).unwrap();
+        assert_eq!(
+            transmit(&mut port, &[0xA5, 0x01], true).unwrap(),
+            Transmission::Collision
+        );
+    }
+
+    #[test]
+    fn test_send_waiting_writes_queued_command() {
+        let pool_protocol = Arc::new(RwLock::new(PoolProtocol::new(
+            0x24,
+            CommandPolicy::default(),
+        )));
+        let handle = pool_protocol
+            .write()
+            .unwrap()
+            .change_circuit("pool", true)
+            .unwrap();
+        let (mut bus, mut port) = MemoryTransport::pipe();
+        let mut scheduler = TransmitScheduler::new(false);
+
+        // The bus was busy just now.
+        scheduler.send_waiting(&mut port, &pool_protocol);
+        assert_eq!(
+            pool_protocol.read().unwrap().get_command_state(handle.id),
+            Some(CommandState::Queued)
+        );
+
+        scheduler.last_activity = Instant::now() - BUS_IDLE_TIME;
+        scheduler.send_waiting(&mut port, &pool_protocol);
+        assert_eq!(
+            pool_protocol.read().unwrap().get_command_state(handle.id),
+            Some(CommandState::Sent)
+        );
+        let mut frame = [0; 13];
+        bus.read_exact(&mut frame).unwrap();
+        assert_eq!(frame[7..11], [0x86, 0x02, 0x06, 0x01]);
+    }
+
+    fn tcp_parameters(address: String) -> config::config_json::PortParameters {
+        serde_json::from_str(&format!(
+            r#"{{"net_address": "{}", "local_echo": false}}"#,
+            address
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tcp_connection_until_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let parameters = tcp_parameters(listener.local_addr().unwrap().to_string());
+        let bridge = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(&[
+                    0xFF, 0x00, 0xFF, 0xA5, 0x01, 0x24, 0x10, 0x01, 0x01, 0x86, 0x01, 0x62,
+                ])
+                .unwrap();
+        });
+
+        let pool_protocol = Arc::new(RwLock::new(PoolProtocol::new(
+            0x24,
+            CommandPolicy::default(),
+        )));
+        let port = open_transport(&parameters).unwrap();
+        let e = run_connection(port, &pool_protocol, false, None);
+        assert!(e.is_connection_lost());
+        assert_eq!(pool_protocol.read().unwrap().get_recent_packets().len(), 1);
+        bridge.join().unwrap();
+    }
+
+    #[test]
+    fn test_reconnects_to_bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let parameters = tcp_parameters(listener.local_addr().unwrap().to_string());
+        let pool_protocol = Arc::new(RwLock::new(PoolProtocol::new(
+            0x24,
+            CommandPolicy::default(),
+        )));
+        thread::spawn(move || port_read_thread(parameters, pool_protocol));
+
+        // The bridge drops the first connection, the thread has to come back.
+        drop(listener.accept().unwrap());
+        listener.set_nonblocking(true).unwrap();
+        let deadline = Instant::now() + RECONNECT_DELAY * 5;
+        loop {
+            match listener.accept() {
+                Ok(_) => break,
+                Err(e) if is_timeout(&e) && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("No reconnect: {}", e),
             }
         }
-        //
     }
 }
@@ -0,0 +1,146 @@
+// Byte streams the protocol can run over: a serial port, a TCP socket or an in-memory pipe.
+
+use serial::SerialPort;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A duplex byte stream. Reads wait at most the read timeout and then fail with a timeout
+/// error, see `is_timeout`.
+pub trait Transport: Read + Write + Send {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+}
+
+/// Different streams report an expired read timeout with different error kinds.
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+impl Transport for serial::SystemPort {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.set_timeout(timeout).map_err(io::Error::from)
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, Some(timeout))
+    }
+}
+
+#[derive(Default)]
+struct Channel {
+    bytes: Mutex<VecDeque<u8>>,
+    available: Condvar,
+}
+
+/// One end of an in-memory pipe, used to run the protocol without hardware.
+pub struct MemoryTransport {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    timeout: Duration,
+}
+
+const DEFAULT_MEMORY_TIMEOUT: Duration = Duration::from_millis(100);
+
+impl MemoryTransport {
+    /// Two connected ends: whatever is written to one is read from the other.
+    pub fn pipe() -> (MemoryTransport, MemoryTransport) {
+        let a = Arc::new(Channel::default());
+        let b = Arc::new(Channel::default());
+        (
+            MemoryTransport {
+                incoming: a.clone(),
+                outgoing: b.clone(),
+                timeout: DEFAULT_MEMORY_TIMEOUT,
+            },
+            MemoryTransport {
+                incoming: b,
+                outgoing: a,
+                timeout: DEFAULT_MEMORY_TIMEOUT,
+            },
+        )
+    }
+
+    /// Reads back everything written to it, as an RS-485 adapter with local echo.
+    pub fn loopback() -> MemoryTransport {
+        let channel = Arc::new(Channel::default());
+        MemoryTransport {
+            incoming: channel.clone(),
+            outgoing: channel,
+            timeout: DEFAULT_MEMORY_TIMEOUT,
+        }
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = Instant::now() + self.timeout;
+        let mut bytes = self.incoming.bytes.lock().unwrap();
+        while bytes.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(ErrorKind::TimedOut, "Read timed out"));
+            }
+            bytes = self
+                .incoming
+                .available
+                .wait_timeout(bytes, deadline - now)
+                .unwrap()
+                .0;
+        }
+        let len = buf.len().min(bytes.len());
+        for (dst, src) in buf.iter_mut().zip(bytes.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.bytes.lock().unwrap().extend(buf);
+        self.outgoing.available.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_pipe() {
+        let (mut a, mut b) = MemoryTransport::pipe();
+        a.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0; 3];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        b.set_read_timeout(Duration::from_millis(10)).unwrap();
+        let e = b.read(&mut buf).unwrap_err();
+        assert!(is_timeout(&e));
+    }
+}
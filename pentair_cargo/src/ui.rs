@@ -1,17 +1,33 @@
 // Interface implementation
 
+use chrono::Local;
 use log::{error, trace};
 use serde::{Deserialize, Serialize};
 
-use crate::pool::{protocol::PacketLogElement, PoolProtocolRW};
 use askama::Template;
 use futures_util::{stream::StreamExt, SinkExt};
+use pentair_cargo::pool::{
+    board::BoardKind,
+    command::{CommandHandle, CommandId, CommandOutcome},
+    message::chlorinator::ChlorinatorState,
+    message::circuit::Circuit,
+    message::circuit::CircuitFunction,
+    message::clock::PanelClock,
+    message::heat::{Body, HeatMode, HeatSettings},
+    message::intellichem::ChemistryState,
+    message::light::{GroupLight, LightGroup, LightState, LightTheme},
+    message::pump_state::{PumpCommand, PumpState},
+    message::schedule::{Schedule, ScheduleTiming},
+    message::system_state::TemperatureUnit,
+    protocol::{ErrorCounters, PacketLogElement, PoolProtocol},
+    PoolProtocolRW,
+};
 
 use axum::{
     extract::ws::{Message, WebSocketUpgrade},
-    extract::{Json, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
 };
 
 // The result structure from the form.
@@ -22,31 +38,395 @@ pub struct ControlInput {
     state: String,
 }
 
+// Heat change from the client, fields that are not set stay as they are.
+#[derive(Deserialize, Debug)]
+pub struct HeatInput {
+    body: Body,
+    setpoint: Option<u8>,
+    mode: Option<HeatMode>,
+}
+
+// A schedule from the client, the id comes from the path or a free slot.
+#[derive(Deserialize, Debug)]
+pub struct ScheduleInput {
+    circuit: u8,
+    #[serde(flatten)]
+    timing: ScheduleTiming,
+}
+
+// Theme or color mode for the IntelliBrite lights.
+#[derive(Deserialize, Debug)]
+pub struct LightInput {
+    theme: LightTheme,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LightGroupInput {
+    lights: Vec<GroupLight>,
+}
+
+// Messages the client sends over the websocket.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ClientMessage {
+    Control(ControlInput),
+    Heat(HeatInput),
+    Light(LightInput),
+}
+
+// Themes offered on the main page, Save and Recall are left to the panel.
+const LIGHT_THEMES: [LightTheme; 16] = [
+    LightTheme::Off,
+    LightTheme::On,
+    LightTheme::ColorSync,
+    LightTheme::ColorSwim,
+    LightTheme::ColorSet,
+    LightTheme::Party,
+    LightTheme::Romance,
+    LightTheme::Caribbean,
+    LightTheme::American,
+    LightTheme::CaliforniaSunset,
+    LightTheme::Royal,
+    LightTheme::Blue,
+    LightTheme::Green,
+    LightTheme::Red,
+    LightTheme::White,
+    LightTheme::Magenta,
+];
+
+#[derive(Serialize, Debug)]
+struct ControlResult {
+    id: CommandId,
+    outcome: CommandOutcome,
+}
+
 pub async fn control_command(
     State(pool_protocol): State<PoolProtocolRW>,
     Json(control_input): Json<ControlInput>,
-) {
+) -> impl IntoResponse {
     trace!("Got client input {:?}", control_input);
 
-    let mut pool_protocol = pool_protocol.write().unwrap();
     let state = control_input.state == "on";
-    pool_protocol.change_circuit(&control_input.control_name, state);
+    let handle = pool_protocol
+        .write()
+        .unwrap()
+        .change_circuit(&control_input.control_name, state);
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+fn outcome_status(outcome: CommandOutcome) -> StatusCode {
+    match outcome {
+        CommandOutcome::Acked => StatusCode::OK,
+        CommandOutcome::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+        CommandOutcome::Failed => StatusCode::BAD_GATEWAY,
+    }
+}
+
+// Waits for the command to finish and reports how it went.
+async fn command_response(handle: CommandHandle) -> Response {
+    // The sender is only dropped without a result when the protocol goes away.
+    let outcome = handle.outcome.await.unwrap_or(CommandOutcome::Failed);
+    trace!("Command {} finished with {:?}", handle.id, outcome);
+    (
+        outcome_status(outcome),
+        Json(ControlResult {
+            id: handle.id,
+            outcome,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn heat_command(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Json(heat_input): Json<HeatInput>,
+) -> impl IntoResponse {
+    trace!("Got heat input {:?}", heat_input);
+    let handle = pool_protocol.write().unwrap().set_heat(
+        heat_input.body,
+        heat_input.setpoint,
+        heat_input.mode,
+    );
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+/// Configuration learned from the panel.
+pub async fn panel_config(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
+    trace!("Calling panel config");
+    Json(pool_protocol.read().unwrap().get_panel_config()).into_response()
+}
+
+pub async fn light_state(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
+    trace!("Calling lights");
+    Json(pool_protocol.read().unwrap().get_lights()).into_response()
+}
+
+pub async fn light_command(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Json(light_input): Json<LightInput>,
+) -> impl IntoResponse {
+    trace!("Got light input {:?}", light_input);
+    let handle = pool_protocol
+        .write()
+        .unwrap()
+        .set_light_theme(light_input.theme);
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+/// Sets the positions and colors of the lights in the group.
+pub async fn light_group_command(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Json(group_input): Json<LightGroupInput>,
+) -> impl IntoResponse {
+    trace!("Got light group {:?}", group_input);
+    let group = LightGroup {
+        lights: group_input.lights,
+    };
+    let handle = pool_protocol.write().unwrap().set_light_group(&group);
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+/// Valves, solar and heaters as configured on the panel.
+pub async fn equipment_config(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
+    trace!("Calling equipment config");
+    Json(pool_protocol.read().unwrap().get_equipment_config()).into_response()
+}
+
+/// Sets the panel clock to the host time.
+pub async fn set_clock(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
+    trace!("Setting the panel clock");
+    let handle = pool_protocol.write().unwrap().set_panel_clock(Local::now());
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+pub async fn list_schedules(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
+    trace!("Calling schedules");
+    Json(pool_protocol.read().unwrap().get_schedules()).into_response()
+}
+
+pub async fn get_schedule(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Path(id): Path<u8>,
+) -> impl IntoResponse {
+    trace!("Calling schedule {}", id);
+    let schedules = pool_protocol.read().unwrap().get_schedules();
+    match schedules.into_iter().find(|schedule| schedule.id == id) {
+        Some(schedule) => Json(schedule).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown schedule {}", id)).into_response(),
+    }
+}
+
+// Writes the schedule to the panel, BAD_REQUEST if it is not valid.
+async fn write_schedule(pool_protocol: PoolProtocolRW, schedule: Schedule) -> Response {
+    let handle = pool_protocol.write().unwrap().set_schedule(&schedule);
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+/// Adds a schedule in the first free slot.
+pub async fn create_schedule(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Json(input): Json<ScheduleInput>,
+) -> impl IntoResponse {
+    trace!("Got new schedule {:?}", input);
+    let Some(id) = pool_protocol.read().unwrap().free_schedule_id() else {
+        return (StatusCode::CONFLICT, "No free schedule slots".to_string()).into_response();
+    };
+    let schedule = Schedule {
+        id,
+        circuit: input.circuit,
+        timing: input.timing,
+    };
+    let mut response = write_schedule(pool_protocol, schedule).await;
+    if response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::CREATED;
+        response.headers_mut().insert(
+            header::LOCATION,
+            format!("/schedules/{}", id).parse().unwrap(),
+        );
+    }
+    response
+}
+
+pub async fn update_schedule(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Path(id): Path<u8>,
+    Json(input): Json<ScheduleInput>,
+) -> impl IntoResponse {
+    trace!("Got schedule {} {:?}", id, input);
+    let schedule = Schedule {
+        id,
+        circuit: input.circuit,
+        timing: input.timing,
+    };
+    write_schedule(pool_protocol, schedule).await
+}
+
+pub async fn delete_schedule(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Path(id): Path<u8>,
+) -> impl IntoResponse {
+    trace!("Deleting schedule {}", id);
+    let handle = pool_protocol.write().unwrap().delete_schedule(id);
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    command_response(handle).await
+}
+
+#[derive(Serialize, Debug)]
+struct PumpInfo {
+    #[serde(flatten)]
+    state: PumpState,
+    /// True when we control the pump instead of the panel.
+    remote: bool,
+}
+
+pub async fn pump_state(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Path(pump_id): Path<u8>,
+) -> impl IntoResponse {
+    trace!("Calling pump {} state", pump_id);
+    let pool_protocol = pool_protocol.read().unwrap();
+    let state = PumpState::pump_address(pump_id).and_then(|address| {
+        pool_protocol
+            .get_pumps()
+            .into_iter()
+            .find(|pump| pump.address == address)
+    });
+    match state {
+        Some(state) => {
+            let remote = pool_protocol.get_remote_pumps().contains(&state.address);
+            Json(PumpInfo { state, remote }).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, format!("Unknown pump {}", pump_id)).into_response(),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct PumpCommandResult {
+    ids: Vec<CommandId>,
+    outcome: CommandOutcome,
+}
+
+pub async fn pump_command(
+    State(pool_protocol): State<PoolProtocolRW>,
+    Path(pump_id): Path<u8>,
+    Json(command): Json<PumpCommand>,
+) -> impl IntoResponse {
+    trace!("Got pump {} command {:?}", pump_id, command);
+    let handles = pool_protocol
+        .write()
+        .unwrap()
+        .pump_command(pump_id, command);
+    let handles = match handles {
+        Ok(handles) => handles,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    // The command is done when all of its packets are, the first problem is reported.
+    let mut ids = Vec::new();
+    let mut outcome = CommandOutcome::Acked;
+    for handle in handles {
+        ids.push(handle.id);
+        let packet_outcome = handle.outcome.await.unwrap_or(CommandOutcome::Failed);
+        if outcome == CommandOutcome::Acked {
+            outcome = packet_outcome;
+        }
+    }
+    (
+        outcome_status(outcome),
+        Json(PumpCommandResult { ids, outcome }),
+    )
+        .into_response()
 }
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct UITemplate<'a> {
-    pub controls: &'a Vec<(String, bool)>,
-    pub temperatures: &'a Vec<(String, f32)>,
+    /// The buttons are the circuit ids, labelled with the names.
+    pub controls: &'a [Circuit],
+    pub temperatures: &'a [(String, f32)],
+    pub temperature_unit: &'a str,
+    pub heating: &'a [(String, bool)],
+    pub heat: Option<&'a HeatSettings>,
+    pub pumps: &'a [PumpState],
+    pub chlorinator: Option<&'a ChlorinatorState>,
+    pub chemistry: Option<&'a ChemistryState>,
+    /// Names of the IntelliBrite circuits, the theme buttons are shown when there are some.
+    pub light_circuits: &'a [String],
+    /// Theme names and whether the theme is the current one.
+    pub light_themes: &'a [(String, bool)],
+}
+
+// The themes for the buttons of the main page.
+fn light_theme_buttons(current: Option<LightTheme>) -> Vec<(String, bool)> {
+    LIGHT_THEMES
+        .iter()
+        .map(|theme| {
+            let name = serde_json::to_value(theme)
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_default();
+            (name, Some(*theme) == current)
+        })
+        .collect()
 }
 
 pub async fn serve_status(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
     trace!("Calling status state request");
     // Read the current state
-    let pool_state = pool_protocol.read().unwrap().get_state();
+    let (pool_state, pumps, chlorinator, chemistry, lights) = {
+        let pool_protocol = pool_protocol.read().unwrap();
+        (
+            pool_protocol.get_state(),
+            pool_protocol.get_pumps(),
+            pool_protocol.get_chlorinator(),
+            pool_protocol.get_chemistry(),
+            pool_protocol.get_lights(),
+        )
+    };
+    let heat = pool_state.get_heat_settings();
+    let light_circuits: Vec<String> = pool_state
+        .get_circuits()
+        .into_iter()
+        .filter(|circuit| circuit.function == CircuitFunction::IntelliBrite)
+        .map(|circuit| circuit.name)
+        .collect();
     let template = UITemplate {
-        controls: &pool_state.get_controls_state(),
+        controls: &pool_state.get_circuits(),
         temperatures: &pool_state.get_temperatures(),
+        temperature_unit: pool_state.get_temperature_unit().symbol(),
+        heating: &pool_state.get_heating_state(),
+        heat: heat.as_ref(),
+        pumps: &pumps,
+        chlorinator: chlorinator.as_ref(),
+        chemistry: chemistry.as_ref(),
+        light_circuits: &light_circuits,
+        light_themes: &light_theme_buttons(lights.theme),
     };
     match template.render() {
         Ok(html) => Html(html).into_response(),
@@ -69,23 +449,73 @@ struct SystemState {
     /// version of the application
     application_version: u32,
 
+    /// Panel family in use.
+    board: BoardKind,
+
     /// Switches state.
     switches: Vec<(String, bool)>,
 
+    /// Every circuit of the panel with its function.
+    circuits: Vec<Circuit>,
+
     /// Temperature sensors.
     temperatures: Vec<(String, f32)>,
+
+    /// Units of the temperatures above.
+    temperature_unit: TemperatureUnit,
+
+    /// Heat sources that are running.
+    heating: Vec<(String, bool)>,
+
+    /// Setpoints and heat modes of the pool and the spa.
+    heat: Option<HeatSettings>,
+
+    /// IntelliFlo pumps.
+    pumps: Vec<PumpState>,
+
+    /// IntelliChlor salt chlorinator.
+    chlorinator: Option<ChlorinatorState>,
+
+    /// IntelliChem readings and dosing.
+    chemistry: Option<ChemistryState>,
+
+    /// Time of the panel.
+    clock: Option<PanelClock>,
+
+    /// Theme and group of the IntelliBrite lights.
+    lights: LightState,
+
+    /// Protocol errors seen on the bus.
+    errors: ErrorCounters,
+}
+
+impl SystemState {
+    fn from_protocol(pool_protocol: &PoolProtocol) -> SystemState {
+        let pool_state = pool_protocol.get_state();
+        SystemState {
+            system_version: 1,
+            application_version: 1,
+            board: pool_protocol.get_board(),
+            switches: pool_state.get_controls_state(),
+            circuits: pool_state.get_circuits(),
+            temperatures: pool_state.get_temperatures(),
+            temperature_unit: pool_state.get_temperature_unit(),
+            heating: pool_state.get_heating_state(),
+            heat: pool_state.get_heat_settings(),
+            pumps: pool_protocol.get_pumps(),
+            chlorinator: pool_protocol.get_chlorinator(),
+            chemistry: pool_protocol.get_chemistry(),
+            clock: pool_protocol.get_clock(),
+            lights: pool_protocol.get_lights(),
+            errors: pool_protocol.get_error_counters(),
+        }
+    }
 }
 
 pub async fn state_json(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
     trace!("Calling state json request");
     // Read the current state
-    let pool_state = pool_protocol.read().unwrap().get_state();
-    let state = SystemState {
-        system_version: 1,
-        application_version: 1,
-        switches: pool_state.get_controls_state(),
-        temperatures: pool_state.get_temperatures(),
-    };
+    let state = SystemState::from_protocol(&pool_protocol.read().unwrap());
     trace!("Replied with a state {:?}", state);
     Json(state).into_response()
 }
@@ -96,16 +526,6 @@ struct LogsTemplate {
     pub logs: Vec<PacketLogElement>,
 }
 
-impl LogsTemplate {
-    // This function is used inside the template.
-    fn vec_u8_to_hex_string(&self, bytes: &Vec<u8>) -> String {
-        bytes
-            .iter() // Get an iterator over the bytes (&u8)
-            .map(|byte| format!("{:02x}", byte)) // Format each byte to a hex String
-            .collect::<String>() // Collect the strings into a single String
-    }
-}
-
 pub async fn log_json(State(pool_protocol): State<PoolProtocolRW>) -> impl IntoResponse {
     trace!("Calling log");
     let template = LogsTemplate {
@@ -137,21 +557,30 @@ pub async fn ws_handler(
             match msg {
                 Message::Text(text) => {
                     trace!("Got a text message: {}", text);
-                    match serde_json::from_str::<ControlInput>(text.as_str()) {
+                    match serde_json::from_str::<ClientMessage>(text.as_str()) {
                         Err(e) => error!("Client sent misforemed json {e:?}"),
-                        Ok(control_input) => {
+                        Ok(ClientMessage::Control(control_input)) => {
                             let mut pool_protocol = pool_protocol.write().unwrap();
                             let state = control_input.state == "on";
-                            pool_protocol.change_circuit(&control_input.control_name, state);
+                            // Rejected commands are logged by the protocol.
+                            let _ =
+                                pool_protocol.change_circuit(&control_input.control_name, state);
+                        }
+                        Ok(ClientMessage::Heat(heat_input)) => {
+                            let _ = pool_protocol.write().unwrap().set_heat(
+                                heat_input.body,
+                                heat_input.setpoint,
+                                heat_input.mode,
+                            );
+                        }
+                        Ok(ClientMessage::Light(light_input)) => {
+                            let _ = pool_protocol
+                                .write()
+                                .unwrap()
+                                .set_light_theme(light_input.theme);
                         }
                     }
-                    let state = pool_protocol.read().unwrap().get_state();
-                    let sstate = SystemState {
-                        system_version: 1,
-                        application_version: 1,
-                        switches: state.get_controls_state(),
-                        temperatures: state.get_temperatures(),
-                    };
+                    let sstate = SystemState::from_protocol(&pool_protocol.read().unwrap());
                     let json = serde_json::to_string(&sstate).unwrap();
                     tx.send(Message::Text(json.into())).await.unwrap();
                 }